@@ -4,7 +4,7 @@ use aoc2024::day03::{day3_part1, day3_part2};
 use aoc2024::day04::{day4_part1, day4_part2};
 use aoc2024::day05::{day5_part1, day5_part2};
 use aoc2024::day06::{day6_part1, day6_part2};
-use aoc2024::day07::{day7_part1, day7_part2};
+use aoc2024::day07::{day7, day7_part1, day7_part2, Operator, Solver, Value};
 use aoc2024::day08::{day8_part1, day8_part2};
 use aoc2024::day09::{day9_part1, day9_part2};
 use aoc2024::day10::{day10_part1, day10_part2};
@@ -14,12 +14,40 @@ use aoc2024::day13::{day13_part1, day13_part2};
 use aoc2024::day14::{day14_part1, day14_part2};
 use aoc2024::day15::{day15_part1, day15_part2};
 use aoc2024::day16::{day16_part1, day16_part2};
-use aoc2024::day17::{day17_part1, day17_part2};
+use aoc2024::day17::{day17_part1, day17_part2, parse_program, CompiledProgram, ProgramState};
 use aoc2024::day18::{day18_part1, day18_part2};
 use aoc2024::day19::{day19_part1, day19_part2};
 use criterion::{criterion_group, criterion_main, Criterion};
 use pprof::criterion::{Output, PProfProfiler};
 
+const D17_BATCH_PROGRAM: &str = "Register A: 0
+Register B: 0
+Register C: 0
+
+Program: 2,4,1,3,7,5,1,5,0,3,4,2,5,5,3,0";
+
+// Solvable day 7 equations with long operand lists. Operands and operators follow fixed
+// patterns that differ per equation, so no two equations are alike.
+fn d7_long_equations(count: usize, len: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| {
+            let operands = (0..len)
+                .map(|j| ((i * 7 + j * j * 5 + i * j) % 9 + 1) as Value)
+                .collect::<Vec<_>>();
+            let target = operands[1..]
+                .iter()
+                .enumerate()
+                .fold(operands[0], |acc, (j, &n)| {
+                    Operator::PART2[(i + j * (j + 1) / 2) % 3]
+                        .apply(acc, n)
+                        .unwrap()
+                });
+            let operands = operands.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+            format!("{}: {}", target, operands.join(" "))
+        })
+        .collect()
+}
+
 fn benchmark_all(c: &mut Criterion) {
     let mut group = c.benchmark_group("aoc2024");
 
@@ -47,52 +75,61 @@ fn benchmark_all(c: &mut Criterion) {
         group.bench_function("d3_part2", |b| b.iter(|| day3_part2()));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d4p1" {
-        group.bench_function("d4_part1", |b| b.iter(|| day4_part1()));
+        group.bench_function("d4_part1", |b| b.iter(|| day4_part1(None)));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d4p2" {
-        group.bench_function("d4_part2", |b| b.iter(|| day4_part2()));
+        group.bench_function("d4_part2", |b| b.iter(|| day4_part2(None)));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d5p1" {
-        group.bench_function("d5_part1", |b| b.iter(|| day5_part1()));
+        group.bench_function("d5_part1", |b| b.iter(|| day5_part1(None)));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d5p2" {
-        group.bench_function("d5_part2", |b| b.iter(|| day5_part2()));
+        group.bench_function("d5_part2", |b| b.iter(|| day5_part2(None)));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d6p1" {
-        group.bench_function("d6_part1", |b| b.iter(|| day6_part1()));
+        group.bench_function("d6_part1", |b| b.iter(|| day6_part1(None)));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d6p2" {
-        group.bench_function("d6_part2", |b| b.iter(|| day6_part2()));
+        group.bench_function("d6_part2", |b| b.iter(|| day6_part2(None)));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d7p1" {
-        group.bench_function("d7_part1", |b| b.iter(|| day7_part1()));
+        group.bench_function("d7_part1", |b| b.iter(|| day7_part1(None)));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d7p2" {
-        group.bench_function("d7_part2", |b| b.iter(|| day7_part2()));
+        group.bench_function("d7_part2", |b| b.iter(|| day7_part2(None)));
+    }
+    if all || std::env::var("PUZZLE").unwrap() == "d7long" {
+        let lines = d7_long_equations(200, 12);
+        group.bench_function("d7_long_forward", |b| {
+            b.iter(|| day7(Some(lines.clone()), &Operator::PART2, Solver::Forward).unwrap())
+        });
+        group.bench_function("d7_long_reverse", |b| {
+            b.iter(|| day7(Some(lines.clone()), &Operator::PART2, Solver::Reverse).unwrap())
+        });
     }
     if all || std::env::var("PUZZLE").unwrap() == "d8p1" {
-        group.bench_function("d8_part1", |b| b.iter(|| day8_part1()));
+        group.bench_function("d8_part1", |b| b.iter(|| day8_part1(None)));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d8p2" {
-        group.bench_function("d8_part2", |b| b.iter(|| day8_part2()));
+        group.bench_function("d8_part2", |b| b.iter(|| day8_part2(None)));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d9p1" {
-        group.bench_function("d9_part1", |b| b.iter(|| day9_part1()));
+        group.bench_function("d9_part1", |b| b.iter(|| day9_part1(None)));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d9p2" {
-        group.bench_function("d9_part2", |b| b.iter(|| day9_part2()));
+        group.bench_function("d9_part2", |b| b.iter(|| day9_part2(None)));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d10p1" {
-        group.bench_function("d10_part1", |b| b.iter(|| day10_part1()));
+        group.bench_function("d10_part1", |b| b.iter(|| day10_part1(None)));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d10p2" {
-        group.bench_function("d10_part2", |b| b.iter(|| day10_part2()));
+        group.bench_function("d10_part2", |b| b.iter(|| day10_part2(None)));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d11p1" {
-        group.bench_function("d11_part1", |b| b.iter(|| day11_part1()));
+        group.bench_function("d11_part1", |b| b.iter(|| day11_part1(None)));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d11p2" {
-        group.bench_function("d11_part2", |b| b.iter(|| day11_part2()));
+        group.bench_function("d11_part2", |b| b.iter(|| day11_part2(None)));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d12p1" {
         group.bench_function("d12_part1", |b| b.iter(|| day12_part1(None)));
@@ -101,10 +138,10 @@ fn benchmark_all(c: &mut Criterion) {
         group.bench_function("d12_part2", |b| b.iter(|| day12_part2(None)));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d13p1" {
-        group.bench_function("d13_part1", |b| b.iter(|| day13_part1()));
+        group.bench_function("d13_part1", |b| b.iter(|| day13_part1(None)));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d13p2" {
-        group.bench_function("d13_part2", |b| b.iter(|| day13_part2()));
+        group.bench_function("d13_part2", |b| b.iter(|| day13_part2(None)));
     }
     if all || std::env::var("PUZZLE").unwrap() == "d14p1" {
         group.bench_function("d14_part1", |b| b.iter(|| day14_part1(None)));
@@ -130,6 +167,48 @@ fn benchmark_all(c: &mut Criterion) {
     if all || std::env::var("PUZZLE").unwrap() == "d17p2" {
         group.bench_function("d17_part2", |b| b.iter(|| day17_part2(None)));
     }
+    if all || std::env::var("PUZZLE").unwrap() == "d17batch" {
+        // interpreter vs compiled program over the same batch of initial A values
+        let program = parse_program(
+            D17_BATCH_PROGRAM.lines().map(|s| s.to_string()).collect(),
+            false,
+        );
+        let reg_a_values = (0..100_000u64)
+            .map(|a| a * 2_654_435_761)
+            .collect::<Vec<_>>();
+        group.bench_function("d17_batch_interpreted", |b| {
+            b.iter(|| {
+                reg_a_values
+                    .iter()
+                    .map(|&reg_a| {
+                        let mut state = ProgramState {
+                            reg_a,
+                            reg_b: 0,
+                            reg_c: 0,
+                            pc: 0,
+                            insns: program.insns.clone(),
+                            output: vec![],
+                            trace: None,
+                            expect_quine: false,
+                        };
+                        state.run().unwrap().len()
+                    })
+                    .sum::<usize>()
+            })
+        });
+        let compiled = CompiledProgram::compile(&program);
+        group.bench_function("d17_batch_compiled", |b| {
+            b.iter(|| {
+                reg_a_values
+                    .iter()
+                    .map(|&reg_a| compiled.run(reg_a).unwrap().len())
+                    .sum::<usize>()
+            })
+        });
+        group.bench_function("d17_batch_compiled_parallel", |b| {
+            b.iter(|| compiled.eval_batch(&reg_a_values))
+        });
+    }
     if all || std::env::var("PUZZLE").unwrap() == "d18p1" {
         group.bench_function("d18_part1", |b| b.iter(|| day18_part1(None)));
     }
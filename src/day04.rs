@@ -1,161 +1,447 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use crate::util::aoc_read_day_lines;
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-enum Letter {
-    X,
-    M,
-    A,
-    S,
+
+// (dx, dy)
+pub const RIGHT: (isize, isize) = (1, 0);
+pub const DOWN: (isize, isize) = (0, 1);
+pub const LEFT: (isize, isize) = (-1, 0);
+pub const UP: (isize, isize) = (0, -1);
+pub const RIGHT_UP: (isize, isize) = (1, -1);
+pub const RIGHT_DOWN: (isize, isize) = (1, 1);
+pub const LEFT_UP: (isize, isize) = (-1, -1);
+pub const LEFT_DOWN: (isize, isize) = (-1, 1);
+
+pub const DIRECTIONS: [(isize, isize); 8] = [
+    RIGHT, DOWN, LEFT, UP, RIGHT_UP, LEFT_UP, RIGHT_DOWN, LEFT_DOWN,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WordMatch {
+    pub word: String,
+    pub x: usize,
+    pub y: usize,
+    pub direction: (isize, isize),
 }
 
-impl Letter {
-    fn from_char(c: char) -> Self {
-        match c {
-            'X' => Letter::X,
-            'M' => Letter::M,
-            'A' => Letter::A,
-            'S' => Letter::S,
-            _ => unreachable!(),
-        }
+impl WordMatch {
+    pub fn cells(&self) -> Vec<(usize, usize)> {
+        (0..self.word.chars().count() as isize)
+            .map(|i| {
+                (
+                    (self.x as isize + i * self.direction.0) as usize,
+                    (self.y as isize + i * self.direction.1) as usize,
+                )
+            })
+            .collect()
+    }
+}
+
+// A small 2D pattern where `.` matches any character, e.g. the X-MAS cross:
+// M.S
+// .A.
+// M.S
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stencil {
+    cells: Vec<Vec<Option<char>>>,
+}
+
+impl Stencil {
+    pub fn parse(pattern: &str) -> Self {
+        let cells = pattern
+            .lines()
+            .map(|line| {
+                line.trim()
+                    .chars()
+                    .map(|c| if c == '.' { None } else { Some(c) })
+                    .collect::<Vec<_>>()
+            })
+            .filter(|row| !row.is_empty())
+            .collect();
+        Stencil { cells }
     }
 
-    fn next(&self) -> Option<Letter> {
-        match self {
-            Letter::X => Some(Letter::M),
-            Letter::M => Some(Letter::A),
-            Letter::A => Some(Letter::S),
-            Letter::S => None,
+    fn width(&self) -> usize {
+        self.cells.iter().map(|row| row.len()).max().unwrap_or(0)
+    }
+
+    fn height(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn rotate_clockwise(&self) -> Stencil {
+        let (width, height) = (self.width(), self.height());
+        let cells = (0..width)
+            .map(|x| {
+                (0..height)
+                    .rev()
+                    .map(|y| self.cells[y].get(x).copied().flatten())
+                    .collect()
+            })
+            .collect();
+        Stencil { cells }
+    }
+
+    // The stencil and its 90/180/270 degree rotations, without duplicates for symmetric stencils.
+    pub fn rotations(&self) -> Vec<Stencil> {
+        let mut rotations: Vec<Stencil> = vec![self.clone()];
+        for _ in 0..3 {
+            let next = rotations.last().unwrap().rotate_clockwise();
+            if !rotations.contains(&next) {
+                rotations.push(next);
+            }
         }
+        rotations
     }
 }
 
-const RIGHT: (isize, isize) = (0, 1);
-const DOWN: (isize, isize) = (1, 0);
-const LEFT: (isize, isize) = (0, -1);
-const UP: (isize, isize) = (-1, 0);
-const RIGHT_UP: (isize, isize) = (-1, 1);
-const RIGHT_DOWN: (isize, isize) = (1, 1);
-const LEFT_UP: (isize, isize) = (-1, -1);
-const LEFT_DOWN: (isize, isize) = (1, -1);
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StencilMatch {
+    // top left corner of the stencil
+    pub x: usize,
+    pub y: usize,
+    // grid cells covered by non-wildcard stencil cells
+    pub cells: Vec<(usize, usize)>,
+}
 
-const DIRECTIONS: [(isize, isize); 8] = [
-    RIGHT, DOWN, LEFT, UP, RIGHT_UP, LEFT_UP, RIGHT_DOWN, LEFT_DOWN,
-];
+// Aho-Corasick automaton over a word list, compiled to a full transition table so scanning is a
+// single table lookup per character.
+pub struct AhoCorasick {
+    // characters used by any word map to 1..; everything else is 0 and always leads back to root
+    alphabet: HashMap<char, usize>,
+    alphabet_size: usize,
+    // node * alphabet_size + symbol -> node
+    transitions: Vec<usize>,
+    // indices of words ending at each node, including those reached through failure links
+    outputs: Vec<Vec<usize>>,
+    word_count: usize,
+}
 
-pub fn day4_part1() -> usize {
-    let lines = aoc_read_day_lines(4);
-    let mut letters: Vec<Vec<Letter>> = Vec::new();
-    let mut x_locations: Vec<(usize, usize)> = Vec::new();
-    for y in 0..lines.len() {
-        let mut row: Vec<Letter> = Vec::new();
-        for x in 0..lines[y].len() {
-            let letter = Letter::from_char(lines[y].chars().nth(x).unwrap());
-            if letter == Letter::X {
-                x_locations.push((x, y));
-            }
-            row.push(letter);
+impl AhoCorasick {
+    pub fn new(words: &[&str]) -> Self {
+        let mut alphabet = HashMap::new();
+        for c in words.iter().flat_map(|w| w.chars()) {
+            let next = alphabet.len() + 1;
+            alphabet.entry(c).or_insert(next);
         }
-        letters.push(row);
-    }
-
-    let mut ways_to_form_xmas = 0;
-    for x_location in x_locations {
-        for direction in DIRECTIONS.iter() {
-            let mut current_letter = Letter::X;
-            let mut current_location = x_location;
-
-            loop {
-                let new_location = (
-                    current_location.0 as isize + direction.0,
-                    current_location.1 as isize + direction.1,
-                );
-                if new_location.0 < 0
-                    || new_location.0 >= letters[0].len() as isize
-                    || new_location.1 < 0
-                    || new_location.1 >= letters.len() as isize
-                {
-                    break;
+        let alphabet_size = alphabet.len() + 1;
+
+        // trie, with usize::MAX as "no edge"
+        let mut transitions = vec![usize::MAX; alphabet_size];
+        let mut outputs: Vec<Vec<usize>> = vec![vec![]];
+        for (word_idx, word) in words.iter().enumerate() {
+            if word.is_empty() {
+                continue;
+            }
+            let mut node = 0;
+            for c in word.chars() {
+                let edge = node * alphabet_size + alphabet[&c];
+                if transitions[edge] == usize::MAX {
+                    transitions[edge] = outputs.len();
+                    transitions.extend(std::iter::repeat_n(usize::MAX, alphabet_size));
+                    outputs.push(vec![]);
                 }
-                let new_letter = letters[new_location.1 as usize][new_location.0 as usize];
-                current_location = (new_location.0 as usize, new_location.1 as usize);
-                if new_letter == current_letter.next().unwrap() {
-                    if new_letter == Letter::S {
-                        ways_to_form_xmas += 1;
-                        break;
+                node = transitions[edge];
+            }
+            outputs[node].push(word_idx);
+        }
+
+        // breadth first so that a node's failure target is always complete before the node
+        let mut fail = vec![0; outputs.len()];
+        let mut queue = VecDeque::new();
+        for root_edge in transitions.iter_mut().take(alphabet_size) {
+            match *root_edge {
+                usize::MAX => *root_edge = 0,
+                child => queue.push_back(child),
+            }
+        }
+        while let Some(node) = queue.pop_front() {
+            let inherited = outputs[fail[node]].clone();
+            outputs[node].extend(inherited);
+            for symbol in 0..alphabet_size {
+                let edge = node * alphabet_size + symbol;
+                let fallback = transitions[fail[node] * alphabet_size + symbol];
+                match transitions[edge] {
+                    usize::MAX => transitions[edge] = fallback,
+                    child => {
+                        fail[child] = fallback;
+                        queue.push_back(child);
                     }
-                    current_letter = new_letter;
-                } else {
-                    break;
                 }
             }
         }
+
+        AhoCorasick {
+            alphabet,
+            alphabet_size,
+            transitions,
+            outputs,
+            word_count: words.len(),
+        }
+    }
+
+    fn symbol(&self, c: Option<char>) -> usize {
+        c.and_then(|c| self.alphabet.get(&c).copied()).unwrap_or(0)
+    }
+
+    // Adds the number of occurrences of each word in `symbols` to `counts`.
+    fn count_into(&self, symbols: impl Iterator<Item = usize>, counts: &mut [usize]) {
+        let mut node = 0;
+        for symbol in symbols {
+            node = self.transitions[node * self.alphabet_size + symbol];
+            for &word_idx in self.outputs[node].iter() {
+                counts[word_idx] += 1;
+            }
+        }
     }
-    ways_to_form_xmas
 }
 
-const OPPOSITE_DIRECTION_QUADRANT: [(isize, isize); 4] = [LEFT_DOWN, RIGHT_UP, LEFT_UP, RIGHT_DOWN];
-
-pub fn day4_part2() -> usize {
-    let lines = aoc_read_day_lines(4);
-    let mut letters: Vec<Vec<Letter>> = Vec::new();
-    let mut a_locations: Vec<(usize, usize)> = Vec::new();
-    for y in 0..lines.len() {
-        let mut row: Vec<Letter> = Vec::new();
-        for x in 0..lines[y].len() {
-            let letter = Letter::from_char(lines[y].chars().nth(x).unwrap());
-            if letter == Letter::A {
-                a_locations.push((x, y));
+pub struct WordSearch {
+    grid: Vec<Vec<char>>,
+    width: usize,
+    height: usize,
+}
+
+impl WordSearch {
+    pub fn from_lines(lines: &[String]) -> Self {
+        let grid = lines
+            .iter()
+            .filter(|line| !line.is_empty())
+            .map(|line| line.chars().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let width = grid.iter().map(|row| row.len()).max().unwrap_or(0);
+        let height = grid.len();
+        WordSearch {
+            grid,
+            width,
+            height,
+        }
+    }
+
+    fn get(&self, x: isize, y: isize) -> Option<char> {
+        if x < 0 || y < 0 {
+            return None;
+        }
+        self.grid
+            .get(y as usize)
+            .and_then(|row| row.get(x as usize))
+            .copied()
+    }
+
+    fn word_at(&self, word: &[char], x: usize, y: usize, direction: (isize, isize)) -> bool {
+        word.iter().enumerate().all(|(i, &c)| {
+            let i = i as isize;
+            self.get(x as isize + i * direction.0, y as isize + i * direction.1) == Some(c)
+        })
+    }
+
+    // Every occurrence of every word in all 8 directions.
+    pub fn find_words(&self, words: &[&str]) -> Vec<WordMatch> {
+        let words = words
+            .iter()
+            .map(|w| (*w, w.chars().collect::<Vec<_>>()))
+            .filter(|(_, chars)| !chars.is_empty())
+            .collect::<Vec<_>>();
+        let mut matches = Vec::new();
+        for y in 0..self.height {
+            for x in 0..self.grid[y].len() {
+                for (word, chars) in words.iter() {
+                    if self.grid[y][x] != chars[0] {
+                        continue;
+                    }
+                    for direction in DIRECTIONS {
+                        if self.word_at(chars, x, y, direction) {
+                            matches.push(WordMatch {
+                                word: word.to_string(),
+                                x,
+                                y,
+                                direction,
+                            });
+                        }
+                    }
+                }
             }
-            row.push(letter);
         }
-        letters.push(row);
-    }
-
-    let mut ways_to_form_xmas = 0;
-    for a_location in a_locations {
-        let letter_in_quadrant = OPPOSITE_DIRECTION_QUADRANT.iter().map(|dir| {
-            let out_of_bounds = a_location.0 as isize + dir.0 < 0
-                || a_location.0 as isize + dir.0 >= letters[0].len() as isize
-                || a_location.1 as isize + dir.1 < 0
-                || a_location.1 as isize + dir.1 >= letters.len() as isize;
-            if out_of_bounds {
-                None
-            } else {
-                Some(
-                    letters[(a_location.1 as isize + dir.1) as usize]
-                        [(a_location.0 as isize + dir.0) as usize],
-                )
+        matches
+    }
+
+    // Number of occurrences of each word (indexed like `words`) in all 8 directions, found in one
+    // linear pass over every row, column and diagonal in both directions. Like `find_words`, a
+    // single-letter word is counted once per direction.
+    pub fn count_words(&self, words: &[&str]) -> Vec<usize> {
+        let automaton = AhoCorasick::new(words);
+        let mut counts = vec![0; automaton.word_count];
+        let (width, height) = (self.width as isize, self.height as isize);
+
+        let mut starts: Vec<((isize, isize), (isize, isize))> = Vec::new();
+        starts.extend((0..height).map(|y| ((0, y), RIGHT)));
+        starts.extend((0..width).map(|x| ((x, 0), DOWN)));
+        starts.extend((0..width).map(|x| ((x, 0), RIGHT_DOWN)));
+        starts.extend((1..height).map(|y| ((0, y), RIGHT_DOWN)));
+        starts.extend((0..width).map(|x| ((x, 0), LEFT_DOWN)));
+        starts.extend((1..height).map(|y| ((width - 1, y), LEFT_DOWN)));
+
+        let mut line = Vec::with_capacity(self.width.max(self.height));
+        for ((mut x, mut y), (dx, dy)) in starts {
+            line.clear();
+            while x >= 0 && x < width && y >= 0 && y < height {
+                line.push(automaton.symbol(self.get(x, y)));
+                x += dx;
+                y += dy;
             }
-        });
+            automaton.count_into(line.iter().copied(), &mut counts);
+            automaton.count_into(line.iter().rev().copied(), &mut counts);
+        }
+        counts
+    }
 
-        let letter_in_quadrant: Vec<Option<Letter>> = letter_in_quadrant.collect();
-        if letter_in_quadrant.iter().any(Option::is_none) {
-            continue;
+    pub fn find_stencil(&self, stencil: &Stencil) -> Vec<StencilMatch> {
+        let (stencil_width, stencil_height) = (stencil.width(), stencil.height());
+        if stencil_width > self.width || stencil_height > self.height {
+            return vec![];
         }
-        let letter_in_quadrant: Vec<Letter> =
-            letter_in_quadrant.iter().filter_map(|l| *l).collect();
-        // there needs to be exactly two M and two S, and 0-1 cant be the same, and 2-3 cant be the same
-        if letter_in_quadrant.len() != 4
-            || letter_in_quadrant
-                .iter()
-                .filter(|l| **l == Letter::M)
-                .count()
-                != 2
-            || letter_in_quadrant
-                .iter()
-                .filter(|l| **l == Letter::S)
-                .count()
-                != 2
-        {
-            continue;
+        let mut matches = Vec::new();
+        for y in 0..=self.height - stencil_height {
+            for x in 0..=self.width - stencil_width {
+                let mut cells = Vec::new();
+                let is_match = stencil.cells.iter().enumerate().all(|(dy, row)| {
+                    row.iter().enumerate().all(|(dx, c)| match c {
+                        None => true,
+                        Some(c) => {
+                            cells.push((x + dx, y + dy));
+                            self.get((x + dx) as isize, (y + dy) as isize) == Some(*c)
+                        }
+                    })
+                });
+                if is_match {
+                    matches.push(StencilMatch { x, y, cells });
+                }
+            }
+        }
+        matches
+    }
+
+    // The grid with every cell not in `cells` replaced by `.`, like the puzzle's examples.
+    pub fn render_highlighted(&self, cells: impl IntoIterator<Item = (usize, usize)>) -> String {
+        let highlighted = cells.into_iter().collect::<HashSet<_>>();
+        let mut out = String::new();
+        for (y, row) in self.grid.iter().enumerate() {
+            for (x, &c) in row.iter().enumerate() {
+                out.push(if highlighted.contains(&(x, y)) {
+                    c
+                } else {
+                    '.'
+                });
+            }
+            out.push('\n');
         }
-        if letter_in_quadrant[0] == letter_in_quadrant[1]
-            || letter_in_quadrant[2] == letter_in_quadrant[3]
-        {
-            continue;
+        out
+    }
+}
+
+const X_MAS: &str = "M.S
+.A.
+M.S";
+
+pub fn day4_part1(test_input: Option<Vec<String>>) -> usize {
+    let lines = test_input.unwrap_or_else(|| aoc_read_day_lines(4));
+    WordSearch::from_lines(&lines).find_words(&["XMAS"]).len()
+}
+
+pub fn day4_part2(test_input: Option<Vec<String>>) -> usize {
+    let lines = test_input.unwrap_or_else(|| aoc_read_day_lines(4));
+    let search = WordSearch::from_lines(&lines);
+    Stencil::parse(X_MAS)
+        .rotations()
+        .iter()
+        .map(|stencil| search.find_stencil(stencil).len())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "MMMSXXMASM
+MSAMXMSMSA
+AMXSXMAAMM
+MSAMASMSMX
+XMASAMXAMM
+XXAMMXXAMA
+SMSMSASXSS
+SAXAMASAAA
+MAMMMXMMMM
+MXMXAXMASX";
+
+    fn example() -> Vec<String> {
+        EXAMPLE.lines().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_day4_part1() {
+        assert_eq!(day4_part1(Some(example())), 18);
+    }
+
+    #[test]
+    fn test_day4_part2() {
+        assert_eq!(day4_part2(Some(example())), 9);
+    }
+
+    #[test]
+    fn test_find_words_positions_and_highlight() {
+        let lines = ["..X...", ".SAMX.", ".A..A.", "XMAS.S", ".X...."]
+            .map(|s| s.to_string())
+            .to_vec();
+        let search = WordSearch::from_lines(&lines);
+        let matches = search.find_words(&["XMAS"]);
+        assert_eq!(matches.len(), 4);
+        assert!(matches.contains(&WordMatch {
+            word: "XMAS".to_string(),
+            x: 0,
+            y: 3,
+            direction: RIGHT,
+        }));
+        assert!(matches.contains(&WordMatch {
+            word: "XMAS".to_string(),
+            x: 4,
+            y: 1,
+            direction: LEFT,
+        }));
+        assert_eq!(
+            search.render_highlighted(matches.iter().flat_map(|m| m.cells())),
+            "..X...\n.SAMX.\n.A..A.\nXMAS.S\n.X....\n"
+        );
+    }
+
+    #[test]
+    fn test_stencil_rotations() {
+        assert_eq!(Stencil::parse(X_MAS).rotations().len(), 4);
+        // symmetric under rotation
+        assert_eq!(Stencil::parse("M.M\n.A.\nM.M").rotations().len(), 1);
+        let matches = WordSearch::from_lines(&["MXS", "XAX", "MXS"].map(|s| s.to_string()))
+            .find_stencil(&Stencil::parse(X_MAS));
+        assert_eq!(matches.len(), 1);
+        assert_eq!(
+            matches[0].cells,
+            vec![(0, 0), (2, 0), (1, 1), (0, 2), (2, 2)]
+        );
+    }
+
+    #[test]
+    fn test_count_words_matches_find_words() {
+        let search = WordSearch::from_lines(&example());
+        let words = ["XMAS", "MAS", "AM", "SAMX", "MM", "XMASX", "Q", "A", ""];
+        let counts = search.count_words(&words);
+        for (word, count) in words.iter().zip(counts) {
+            assert_eq!(count, search.find_words(&[word]).len(), "{}", word);
         }
-        ways_to_form_xmas += 1;
+        assert_eq!(search.count_words(&["XMAS"]), vec![18]);
+    }
+
+    #[test]
+    fn test_count_words_overlapping_and_duplicate_words() {
+        let search = WordSearch::from_lines(&["ABABA".to_string()]);
+        // ABA twice forwards and twice backwards; BAB once in each direction
+        assert_eq!(search.count_words(&["ABA", "BAB", "ABA"]), vec![4, 2, 4]);
     }
-    ways_to_form_xmas
 }
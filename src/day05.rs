@@ -1,5 +1,6 @@
 use crate::util::aoc_read_day_lines;
-use std::collections::{HashMap, HashSet};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
 
 // xx|yy
 #[derive(Debug)]
@@ -25,107 +26,309 @@ impl Rule {
 
 type Update = Vec<usize>;
 
-pub fn day5_part1() -> usize {
-    let lines = aoc_read_day_lines(5);
-
-    let mut rules: Vec<Rule> = Vec::new();
-    let mut updates: Vec<Update> = Vec::new();
+// Ordering rules as an adjacency bitmatrix over the pages that appear in any rule, numbered
+// densely in order of appearance: row `fst` has bit `snd` set for every rule `fst|snd`. A rule
+// `a|a` can never be satisfied, so an update containing `a` is invalid and cannot be repaired.
+pub struct PageOrdering {
+    // page number to row and bit
+    index: HashMap<usize, usize>,
+    successors: Vec<u64>,
+    words_per_row: usize,
+}
 
-    // read until blank line to get rules, one per line
-    let mut i = 0;
-    for line in lines.iter() {
-        if line.is_empty() {
-            break;
+impl PageOrdering {
+    fn from_rules(rules: &[Rule]) -> Self {
+        let mut index = HashMap::new();
+        for r in rules {
+            for page in [r.fst, r.snd] {
+                let next = index.len();
+                index.entry(page).or_insert(next);
+            }
+        }
+        let words_per_row = index.len().div_ceil(64);
+        let mut successors = vec![0u64; index.len() * words_per_row];
+        for r in rules {
+            let (fst, snd) = (index[&r.fst], index[&r.snd]);
+            successors[fst * words_per_row + snd / 64] |= 1 << (snd % 64);
+        }
+        PageOrdering {
+            index,
+            successors,
+            words_per_row,
         }
-        let rule: Rule = Rule::from_str(&line);
-        rules.push(rule);
-        i += 1;
     }
 
-    // rest are commaseparated updates
-    for line in lines.iter().skip(i + 1) {
-        let update: Update = line
-            .split(',')
-            .map(|s| s.parse().expect("update"))
-            .collect();
-        updates.push(update);
+    // Parses the `fst|snd` lines up to the first blank line.
+    pub fn from_lines(lines: &[String]) -> Self {
+        let rules = lines
+            .iter()
+            .take_while(|line| !line.is_empty())
+            .map(|line| Rule::from_str(line))
+            .collect::<Vec<_>>();
+        Self::from_rules(&rules)
     }
 
-    let mut valids = Vec::new();
-    for u in updates.iter() {
-        let mut valid = true;
-        let positions_hashmap: HashMap<usize, usize> = u
-            .iter()
-            .enumerate()
-            .map(|(i, &v)| (v, i as usize))
-            .collect();
-        for r in rules.iter() {
-            if !positions_hashmap.contains_key(&r.fst) || !positions_hashmap.contains_key(&r.snd) {
+    fn row(&self, idx: usize) -> &[u64] {
+        &self.successors[idx * self.words_per_row..(idx + 1) * self.words_per_row]
+    }
+
+    // Whether there is a rule `fst|snd`.
+    pub fn must_precede(&self, fst: usize, snd: usize) -> bool {
+        match (self.index.get(&fst), self.index.get(&snd)) {
+            (Some(&fst), Some(&snd)) => self.row(fst)[snd / 64] & (1 << (snd % 64)) != 0,
+            _ => false,
+        }
+    }
+
+    // O(n) in the update length: a page is misplaced iff one of its successors was already seen.
+    // The page itself counts as seen, which catches a rule `a|a`.
+    pub fn is_valid(&self, update: &[usize]) -> bool {
+        let mut seen = vec![0u64; self.words_per_row];
+        for page in update {
+            let Some(&page) = self.index.get(page) else {
                 continue;
+            };
+            seen[page / 64] |= 1 << (page % 64);
+            if self
+                .row(page)
+                .iter()
+                .zip(seen.iter())
+                .any(|(s, v)| s & v != 0)
+            {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Every rule `fst|snd` broken by the update, i.e. `snd` appears before `fst`, and every rule
+    // `a|a` for a page `a` in it.
+    pub fn violations(&self, update: &[usize]) -> Vec<(usize, usize)> {
+        let mut violations = Vec::new();
+        for (i, &earlier) in update.iter().enumerate() {
+            if self.must_precede(earlier, earlier) {
+                violations.push((earlier, earlier));
             }
-            let fst_pos = positions_hashmap[&r.fst];
-            let snd_pos = positions_hashmap[&r.snd];
-            if fst_pos > snd_pos {
-                valid = false;
-                break;
+            for &later in update[i + 1..].iter() {
+                if self.must_precede(later, earlier) {
+                    violations.push((later, earlier));
+                }
             }
         }
-        if valid {
-            valids.push(u);
+        violations
+    }
+
+    // Reorders the update with a topological sort over the rules between its pages. Pages keep
+    // their original relative order wherever the rules allow it. If the rules restricted to these
+    // pages contain cycles no valid order exists, and the cycles are returned instead.
+    pub fn repair(&self, update: &[usize]) -> Result<Update, Vec<Vec<usize>>> {
+        let mut in_degree = update
+            .iter()
+            .map(|&page| {
+                update
+                    .iter()
+                    .filter(|&&other| self.must_precede(other, page))
+                    .count()
+            })
+            .collect::<Vec<_>>();
+
+        let mut ready = (0..update.len())
+            .filter(|&i| in_degree[i] == 0)
+            .map(Reverse)
+            .collect::<BinaryHeap<_>>();
+        let mut sorted = Vec::with_capacity(update.len());
+        while let Some(Reverse(i)) = ready.pop() {
+            sorted.push(update[i]);
+            for (j, &page) in update.iter().enumerate() {
+                if self.must_precede(update[i], page) {
+                    in_degree[j] -= 1;
+                    if in_degree[j] == 0 {
+                        ready.push(Reverse(j));
+                    }
+                }
+            }
+        }
+
+        if sorted.len() == update.len() {
+            Ok(sorted)
+        } else {
+            Err(self.cycles(update))
         }
     }
 
-    let sum_of_middle_nums: usize = valids.iter().map(|u| u[u.len() / 2]).sum();
-    sum_of_middle_nums
+    // Cycles among the rules restricted to `pages`, one per back edge found by a depth first search.
+    pub fn cycles(&self, pages: &[usize]) -> Vec<Vec<usize>> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Mark {
+            Unvisited,
+            OnStack,
+            Done,
+        }
+
+        let mut marks = vec![Mark::Unvisited; pages.len()];
+        let mut cycles = Vec::new();
+        for root in 0..pages.len() {
+            if marks[root] != Mark::Unvisited {
+                continue;
+            }
+            // (node, next successor index to try)
+            let mut stack = vec![(root, 0)];
+            marks[root] = Mark::OnStack;
+            while let Some(&mut (node, ref mut next)) = stack.last_mut() {
+                let Some(succ) =
+                    (*next..pages.len()).find(|&j| self.must_precede(pages[node], pages[j]))
+                else {
+                    marks[node] = Mark::Done;
+                    stack.pop();
+                    continue;
+                };
+                *next = succ + 1;
+                match marks[succ] {
+                    Mark::Unvisited => {
+                        marks[succ] = Mark::OnStack;
+                        stack.push((succ, 0));
+                    }
+                    Mark::OnStack => {
+                        let start = stack.iter().position(|&(n, _)| n == succ).unwrap();
+                        cycles.push(stack[start..].iter().map(|&(n, _)| pages[n]).collect());
+                    }
+                    Mark::Done => {}
+                }
+            }
+        }
+        cycles
+    }
 }
 
-pub fn day5_part2() -> usize {
-    let lines = aoc_read_day_lines(5);
-    let rules: Vec<Rule> = lines
+fn parse_input(lines: &[String]) -> (PageOrdering, Vec<Update>) {
+    let ordering = PageOrdering::from_lines(lines);
+    let updates = lines
         .iter()
-        .take_while(|line| !line.is_empty())
-        .map(|line| Rule::from_str(line))
-        .collect();
-    let mut updates: Vec<Update> = lines
-        .iter()
-        .skip(rules.len() + 1)
+        .skip_while(|line| !line.is_empty())
+        .filter(|line| !line.is_empty())
         .map(|line| {
             line.split(',')
                 .map(|s| s.parse().expect("update"))
                 .collect()
         })
         .collect();
-    let mut invalids: Vec<_> = updates
-        .iter_mut()
-        .filter(|u| {
-            rules.iter().any(|r| {
-                let fst_pos = u.iter().position(|&x| x == r.fst);
-                let snd_pos = u.iter().position(|&x| x == r.snd);
-
-                match (fst_pos, snd_pos) {
-                    (Some(fst), Some(snd)) => fst >= snd,
-                    _ => false,
-                }
-            })
+    (ordering, updates)
+}
+
+pub fn day5_part1(test_input: Option<Vec<String>>) -> usize {
+    let lines = test_input.unwrap_or_else(|| aoc_read_day_lines(5));
+    let (ordering, updates) = parse_input(&lines);
+
+    updates
+        .iter()
+        .filter(|u| ordering.is_valid(u))
+        .map(|u| u[u.len() / 2])
+        .sum()
+}
+
+pub fn day5_part2(test_input: Option<Vec<String>>) -> usize {
+    let lines = test_input.unwrap_or_else(|| aoc_read_day_lines(5));
+    let (ordering, updates) = parse_input(&lines);
+
+    updates
+        .iter()
+        .filter(|u| !ordering.is_valid(u))
+        .map(|u| {
+            let repaired = ordering
+                .repair(u)
+                .unwrap_or_else(|cycles| panic!("no valid order for {:?}: {:?}", u, cycles));
+            repaired[repaired.len() / 2]
         })
-        .collect();
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for invalid in invalids.iter_mut() {
-        invalid.sort_by(|a, b| {
-            let should = should_swap(*a, *b, &rules);
-            should.cmp(&!should)
-        });
+    const EXAMPLE: &str = "47|53
+97|13
+97|61
+97|47
+75|29
+61|13
+75|53
+29|13
+97|29
+53|29
+61|53
+97|53
+61|29
+47|13
+75|47
+97|75
+47|61
+75|61
+47|29
+75|13
+53|13
+
+75,47,61,53,29
+97,61,53,29,13
+75,29,13
+75,97,47,61,53
+61,13,29
+97,13,75,29,47";
+
+    fn example() -> Vec<String> {
+        EXAMPLE.lines().map(|s| s.to_string()).collect()
     }
 
-    let sum_of_middle_nums: usize = invalids.iter().map(|u| u[u.len() / 2]).sum();
-    sum_of_middle_nums
-}
+    #[test]
+    fn test_day5_part1() {
+        assert_eq!(day5_part1(Some(example())), 143);
+    }
 
-fn should_swap(a: usize, b: usize, rules: &Vec<Rule>) -> bool {
-    for r in rules.iter() {
-        if r.fst == b && r.snd == a {
-            return true;
-        }
+    #[test]
+    fn test_day5_part2() {
+        assert_eq!(day5_part2(Some(example())), 123);
+    }
+
+    #[test]
+    fn test_violations_and_repair() {
+        let (ordering, _) = parse_input(&example());
+        assert_eq!(ordering.violations(&[75, 97, 47, 61, 53]), vec![(97, 75)]);
+        assert_eq!(ordering.violations(&[61, 13, 29]), vec![(29, 13)]);
+        assert_eq!(
+            ordering.repair(&[97, 13, 75, 29, 47]),
+            Ok(vec![97, 75, 47, 29, 13])
+        );
+        assert!(ordering.cycles(&[97, 13, 75, 29, 47]).is_empty());
+    }
+
+    #[test]
+    fn test_cycles() {
+        let lines = ["1|2", "2|3", "3|1", "3|4", "5|5"].map(|s| s.to_string());
+        let ordering = PageOrdering::from_lines(&lines);
+        assert_eq!(ordering.cycles(&[1, 2, 3, 4]), vec![vec![1, 2, 3]]);
+        assert_eq!(ordering.repair(&[4, 3, 2, 1]), Err(vec![vec![3, 1, 2]]));
+        assert_eq!(ordering.repair(&[4, 5]), Err(vec![vec![5]]));
+        // a self-loop makes the update invalid as well as unrepairable
+        assert!(!ordering.is_valid(&[4, 5]));
+        assert!(!ordering.is_valid(&[5]));
+        assert!(ordering.is_valid(&[4, 6]));
+        assert_eq!(ordering.violations(&[4, 5]), vec![(5, 5)]);
+        // the cycle only exists when all of its pages are in the update
+        assert_eq!(ordering.repair(&[4, 3, 2]), Ok(vec![2, 3, 4]));
+    }
+
+    #[test]
+    fn test_sparse_page_numbers() {
+        // memory depends on the number of distinct pages, not on the largest one
+        let lines = ["1|4000000000", "4000000000|77"].map(|s| s.to_string());
+        let ordering = PageOrdering::from_lines(&lines);
+        assert!(ordering.must_precede(1, 4000000000));
+        assert!(!ordering.must_precede(4000000000, 1));
+        assert!(ordering.is_valid(&[1, 4000000000, 77]));
+        assert!(!ordering.is_valid(&[77, 1, 4000000000]));
+        assert_eq!(
+            ordering.repair(&[77, 4000000000, 1, 12]),
+            Ok(vec![1, 4000000000, 77, 12])
+        );
     }
-    false
 }
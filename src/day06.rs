@@ -1,14 +1,11 @@
+use std::collections::HashMap;
+
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
 use crate::util::aoc_read_day_lines;
 
-const WIDTH: usize = 130;
-const HEIGHT: usize = 130;
-const ARRAY_SIZE: usize = WIDTH * HEIGHT;
-const VISITED_ARRAY_SIZE: usize = 4 * ARRAY_SIZE;
-
-#[derive(Debug, Clone, Copy)]
-enum Direction {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
     Up,
     Down,
     Left,
@@ -76,52 +73,82 @@ impl Guard {
             _ => unreachable!(),
         }
     }
+
+    fn to_char(self) -> char {
+        match self.direction {
+            Direction::Up => '^',
+            Direction::Down => 'v',
+            Direction::Right => '>',
+            Direction::Left => '<',
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathStep {
+    pub x: usize,
+    pub y: usize,
+    pub direction: Direction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopObstacle {
+    pub x: usize,
+    pub y: usize,
+    // cells walked per lap of the loop the obstacle causes
+    pub cycle_length: usize,
+}
+
+#[derive(Debug, Clone)]
 struct VisitedWhileFacingDirection {
-    vec: [bool; VISITED_ARRAY_SIZE],
+    vec: Vec<bool>,
+    width: usize,
 }
 
 impl VisitedWhileFacingDirection {
-    fn new() -> Self {
+    fn new(width: usize, height: usize) -> Self {
         Self {
-            vec: [false; VISITED_ARRAY_SIZE],
+            vec: vec![false; 4 * width * height],
+            width,
         }
     }
 
     fn get(&self, x: usize, y: usize, direction: Direction) -> bool {
-        self.vec[4 * (y * WIDTH + x) + direction as usize]
+        self.vec[4 * (y * self.width + x) + direction as usize]
     }
 
     fn set(&mut self, x: usize, y: usize, direction: Direction, value: bool) {
-        self.vec[4 * (y * WIDTH + x) + direction as usize] = value;
+        self.vec[4 * (y * self.width + x) + direction as usize] = value;
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 struct FlatVec<T> {
-    vec: [T; ARRAY_SIZE],
+    vec: Vec<T>,
+    width: usize,
 }
 
 impl<T: Copy + Default> FlatVec<T> {
-    fn new() -> Self {
+    fn new(width: usize, height: usize) -> Self {
         Self {
-            vec: [T::default(); ARRAY_SIZE],
+            vec: vec![T::default(); width * height],
+            width,
         }
     }
 
     fn get(&self, x: usize, y: usize) -> &T {
-        &self.vec[y * WIDTH + x]
+        &self.vec[y * self.width + x]
     }
 
     fn set(&mut self, x: usize, y: usize, value: T) {
-        self.vec[y * WIDTH + x] = value;
+        self.vec[y * self.width + x] = value;
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-struct Map {
+#[derive(Debug, Clone)]
+pub struct Map {
+    width: usize,
+    height: usize,
     coords: FlatVec<Terrain>,
     visited: FlatVec<bool>,
     visited_while_facing_direction: VisitedWhileFacingDirection,
@@ -136,9 +163,19 @@ enum EndState {
 }
 
 impl Map {
-    fn from_lines(lines: &[String]) -> Self {
-        let mut coords = FlatVec::new();
-        let mut visited = FlatVec::new();
+    pub fn from_lines(lines: &[String]) -> Self {
+        let lines = lines
+            .iter()
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>();
+        let height = lines.len();
+        let width = lines.first().map_or(0, |line| line.len());
+        assert!(
+            lines.iter().all(|line| line.len() == width),
+            "map rows must all have the same width"
+        );
+        let mut coords = FlatVec::new(width, height);
+        let mut visited = FlatVec::new(width, height);
         let mut visited_count = 0;
         let mut guard = None;
 
@@ -156,9 +193,11 @@ impl Map {
         }
 
         Map {
+            width,
+            height,
             coords,
             visited,
-            visited_while_facing_direction: VisitedWhileFacingDirection::new(),
+            visited_while_facing_direction: VisitedWhileFacingDirection::new(width, height),
             visited_count,
             guard: guard.unwrap(),
         }
@@ -173,37 +212,95 @@ impl Map {
         }
     }
 
-    fn run_until_exits_or_loops(&mut self) -> EndState {
-        self.visited_while_facing_direction.set(
-            self.guard.x,
-            self.guard.y,
-            self.guard.direction,
-            true,
-        );
-        while self.move_guard() {
-            if self.visited_while_facing_direction.get(
-                self.guard.x,
-                self.guard.y,
-                self.guard.direction,
-            ) {
-                return EndState::Loops;
+    // The guard's route in order, one entry per position and facing (so turning in place adds an
+    // entry), until it leaves the map or would repeat a state.
+    pub fn path(&self) -> Vec<PathStep> {
+        self.path_with_obstacle(None)
+    }
+
+    pub fn path_with_obstacle(&self, obstacle: Option<(usize, usize)>) -> Vec<PathStep> {
+        let mut map = self.clone();
+        if let Some((x, y)) = obstacle {
+            map.coords.set(x, y, Terrain::Wall);
+        }
+        let mut path = Vec::new();
+        loop {
+            let guard = map.guard;
+            if map
+                .visited_while_facing_direction
+                .get(guard.x, guard.y, guard.direction)
+            {
+                break;
+            }
+            map.visited_while_facing_direction
+                .set(guard.x, guard.y, guard.direction, true);
+            path.push(PathStep {
+                x: guard.x,
+                y: guard.y,
+                direction: guard.direction,
+            });
+            if !map.move_guard() {
+                break;
             }
-            self.visited_while_facing_direction.set(
-                self.guard.x,
-                self.guard.y,
-                self.guard.direction,
-                true,
-            );
         }
-        EndState::Exits
+        path
+    }
+
+    // Every position where a single extra obstacle makes the guard loop, in reading order.
+    pub fn loop_obstacles(&self) -> Vec<LoopObstacle> {
+        let jump_table = JumpTable::new(self);
+        obstacle_candidates(self)
+            .par_iter()
+            .filter_map(|&(x, y)| {
+                jump_table
+                    .cycle_length_with_obstacle(self.guard, (x, y))
+                    .map(|cycle_length| LoopObstacle { x, y, cycle_length })
+            })
+            .collect()
+    }
+
+    // Draws the path like the puzzle text: `|` and `-` for vertical and horizontal movement, `+`
+    // where the guard turns or crosses its own path, and `O` for the extra obstacle.
+    pub fn render_path(&self, path: &[PathStep], obstacle: Option<(usize, usize)>) -> String {
+        // bit 0: moved vertically, bit 1: moved horizontally
+        let mut movement = FlatVec::<u8>::new(self.width, self.height);
+        for step in path {
+            let bit = match step.direction {
+                Direction::Up | Direction::Down => 1,
+                Direction::Left | Direction::Right => 2,
+            };
+            movement.set(step.x, step.y, movement.get(step.x, step.y) | bit);
+        }
+
+        let mut out = String::with_capacity((self.width + 1) * self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                out.push(if Some((x, y)) == obstacle {
+                    'O'
+                } else if *self.coords.get(x, y) == Terrain::Wall {
+                    '#'
+                } else if (x, y) == (self.guard.x, self.guard.y) {
+                    self.guard.to_char()
+                } else {
+                    match movement.get(x, y) {
+                        1 => '|',
+                        2 => '-',
+                        3 => '+',
+                        _ => '.',
+                    }
+                });
+            }
+            out.push('\n');
+        }
+        out
     }
 
     fn move_guard(&mut self) -> bool {
         let is_exiting = match self.guard.direction {
             Direction::Up => self.guard.y == 0,
-            Direction::Down => self.guard.y == HEIGHT - 1,
+            Direction::Down => self.guard.y == self.height - 1,
             Direction::Left => self.guard.x == 0,
-            Direction::Right => self.guard.x == WIDTH - 1,
+            Direction::Right => self.guard.x == self.width - 1,
         };
         if is_exiting {
             return false;
@@ -240,36 +337,319 @@ impl Map {
     }
 }
 
-pub fn day6_part1() -> usize {
-    let lines = aoc_read_day_lines(6);
+// For every cell and direction, the cell where the guard stops when walking straight until the
+// next obstacle, so a simulation only has to visit turning points.
+struct JumpTable {
+    width: usize,
+    // 4 * cell + direction -> cell in front of the next obstacle, or usize::MAX when the guard
+    // walks off the map instead
+    stops: Vec<usize>,
+}
+
+impl JumpTable {
+    fn new(map: &Map) -> Self {
+        let (width, height) = (map.width, map.height);
+        let mut stops = vec![usize::MAX; 4 * width * height];
+        let is_wall = |x: usize, y: usize| *map.coords.get(x, y) == Terrain::Wall;
+        let idx =
+            |x: usize, y: usize, direction: Direction| 4 * (y * width + x) + direction as usize;
+
+        // each stop is either the neighbouring cell's stop or the cell itself when the
+        // neighbour is a wall, so sweep against the direction of travel
+        for x in 0..width {
+            for y in 1..height {
+                stops[idx(x, y, Direction::Up)] = if is_wall(x, y - 1) {
+                    y * width + x
+                } else {
+                    stops[idx(x, y - 1, Direction::Up)]
+                };
+            }
+            for y in (0..height - 1).rev() {
+                stops[idx(x, y, Direction::Down)] = if is_wall(x, y + 1) {
+                    y * width + x
+                } else {
+                    stops[idx(x, y + 1, Direction::Down)]
+                };
+            }
+        }
+        for y in 0..height {
+            for x in 1..width {
+                stops[idx(x, y, Direction::Left)] = if is_wall(x - 1, y) {
+                    y * width + x
+                } else {
+                    stops[idx(x - 1, y, Direction::Left)]
+                };
+            }
+            for x in (0..width - 1).rev() {
+                stops[idx(x, y, Direction::Right)] = if is_wall(x + 1, y) {
+                    y * width + x
+                } else {
+                    stops[idx(x + 1, y, Direction::Right)]
+                };
+            }
+        }
+
+        JumpTable { width, stops }
+    }
+
+    // Where the guard stops walking from (x, y), with one extra obstacle placed on the map. The
+    // extra obstacle can only change the result if it lies on the segment being jumped over, so
+    // it is checked here instead of rebuilding the table for every candidate.
+    fn jump(
+        &self,
+        x: usize,
+        y: usize,
+        direction: Direction,
+        obstacle: (usize, usize),
+    ) -> Option<(usize, usize)> {
+        let stop = self.stops[4 * (y * self.width + x) + direction as usize];
+        let stop = (stop != usize::MAX).then(|| (stop % self.width, stop / self.width));
+        let (ox, oy) = obstacle;
+        let blocked = match direction {
+            Direction::Up => ox == x && oy < y && stop.is_none_or(|(_, sy)| oy >= sy),
+            Direction::Down => ox == x && oy > y && stop.is_none_or(|(_, sy)| oy <= sy),
+            Direction::Left => oy == y && ox < x && stop.is_none_or(|(sx, _)| ox >= sx),
+            Direction::Right => oy == y && ox > x && stop.is_none_or(|(sx, _)| ox <= sx),
+        };
+        if !blocked {
+            return stop;
+        }
+        Some(match direction {
+            Direction::Up => (ox, oy + 1),
+            Direction::Down => (ox, oy - 1),
+            Direction::Left => (ox + 1, oy),
+            Direction::Right => (ox - 1, oy),
+        })
+    }
+
+    // Simulates the guard from turning point to turning point. `seen` is scratch space of
+    // 4 * width * height entries, where entries equal to `generation` count as visited.
+    fn run_with_obstacle(
+        &self,
+        guard: Guard,
+        obstacle: (usize, usize),
+        seen: &mut [u32],
+        generation: u32,
+    ) -> EndState {
+        let (mut x, mut y, mut direction) = (guard.x, guard.y, guard.direction);
+        while let Some((stop_x, stop_y)) = self.jump(x, y, direction, obstacle) {
+            (x, y, direction) = (stop_x, stop_y, direction.turn_right());
+            let idx = 4 * (y * self.width + x) + direction as usize;
+            if seen[idx] == generation {
+                return EndState::Loops;
+            }
+            seen[idx] = generation;
+        }
+        EndState::Exits
+    }
+
+    // Like `run_with_obstacle`, but also measures the loop: the number of cells walked between two
+    // visits of the same turning point.
+    fn cycle_length_with_obstacle(&self, guard: Guard, obstacle: (usize, usize)) -> Option<usize> {
+        let (mut x, mut y, mut direction) = (guard.x, guard.y, guard.direction);
+        let mut turning_points: Vec<(usize, usize)> = Vec::new();
+        let mut first_seen: HashMap<(usize, usize, usize), usize> = HashMap::new();
+        while let Some((stop_x, stop_y)) = self.jump(x, y, direction, obstacle) {
+            (x, y, direction) = (stop_x, stop_y, direction.turn_right());
+            turning_points.push((x, y));
+            if let Some(&start) = first_seen.get(&(x, y, direction as usize)) {
+                return Some(
+                    turning_points[start..]
+                        .windows(2)
+                        .map(|w| w[0].0.abs_diff(w[1].0) + w[0].1.abs_diff(w[1].1))
+                        .sum(),
+                );
+            }
+            first_seen.insert((x, y, direction as usize), turning_points.len() - 1);
+        }
+        None
+    }
+}
+
+pub fn day6_part1(test_input: Option<Vec<String>>) -> usize {
+    let lines = test_input.unwrap_or_else(|| aoc_read_day_lines(6));
     let mut map = Map::from_lines(&lines);
     map.run();
     map.visited_count
 }
 
-pub fn day6_part2() -> usize {
-    let lines = aoc_read_day_lines(6);
-    let map = Map::from_lines(&lines);
-
+// Positions where an extra obstacle could change the guard's route: everything the guard visits
+// except its starting position.
+fn obstacle_candidates(map: &Map) -> Vec<(usize, usize)> {
     let mut initial_clone = map.clone();
     initial_clone.run();
     let initial_visited = initial_clone.visited;
 
-    let coords: Vec<(usize, usize)> = (0..HEIGHT)
-        .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+    let width = map.width;
+    (0..map.height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
         .filter(|&(x, y)| {
             *initial_visited.get(x, y)
                 && *map.coords.get(x, y) != Terrain::Wall
                 && (x, y) != (map.guard.x, map.guard.y)
         })
-        .collect();
+        .collect()
+}
+
+pub fn day6_part2(test_input: Option<Vec<String>>) -> usize {
+    let lines = test_input.unwrap_or_else(|| aoc_read_day_lines(6));
+    let map = Map::from_lines(&lines);
+    let jump_table = JumpTable::new(&map);
+    let seen_len = 4 * map.width * map.height;
 
-    coords
+    obstacle_candidates(&map)
         .par_iter()
-        .map(|&(x, y)| {
-            let mut map_clone = map.clone();
-            map_clone.coords.set(x, y, Terrain::Wall);
-            map_clone.run_until_exits_or_loops() as usize
-        })
+        .map_init(
+            || (vec![0u32; seen_len], 0u32),
+            |(seen, generation), &obstacle| {
+                *generation += 1;
+                jump_table.run_with_obstacle(map.guard, obstacle, seen, *generation) as usize
+            },
+        )
         .sum()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "....#.....
+.........#
+..........
+..#.......
+.......#..
+..........
+.#..^.....
+........#.
+#.........
+......#...";
+
+    fn example() -> Vec<String> {
+        EXAMPLE.lines().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_day6_part1() {
+        assert_eq!(day6_part1(Some(example())), 41);
+    }
+
+    #[test]
+    fn test_day6_part2() {
+        assert_eq!(day6_part2(Some(example())), 6);
+    }
+
+    // The guard walked cell by cell with the extra obstacle, for checking the jump table.
+    fn end_state_cell_by_cell(map: &Map, (x, y): (usize, usize)) -> EndState {
+        let mut map = map.clone();
+        map.coords.set(x, y, Terrain::Wall);
+        let mut seen = VisitedWhileFacingDirection::new(map.width, map.height);
+        loop {
+            let guard = map.guard;
+            if seen.get(guard.x, guard.y, guard.direction) {
+                return EndState::Loops;
+            }
+            seen.set(guard.x, guard.y, guard.direction, true);
+            if !map.move_guard() {
+                return EndState::Exits;
+            }
+        }
+    }
+
+    #[test]
+    fn test_jump_table_matches_cell_by_cell() {
+        let map = Map::from_lines(&example());
+        let jump_table = JumpTable::new(&map);
+        let mut seen = vec![0; 4 * map.width * map.height];
+        let candidates = obstacle_candidates(&map);
+        for (generation, &(x, y)) in candidates.iter().enumerate() {
+            assert_eq!(
+                jump_table.run_with_obstacle(map.guard, (x, y), &mut seen, generation as u32 + 1),
+                end_state_cell_by_cell(&map, (x, y)),
+                "obstacle at {:?}",
+                (x, y)
+            );
+        }
+    }
+
+    #[test]
+    fn test_non_square_map() {
+        let lines = ["..#...", "......", "..^..."]
+            .map(|s| s.to_string())
+            .to_vec();
+        // up until the wall at (2, 0), then right along the middle row until exiting
+        assert_eq!(day6_part1(Some(lines)), 5);
+    }
+
+    #[test]
+    fn test_path() {
+        let map = Map::from_lines(&example());
+        let path = map.path();
+        assert_eq!(
+            path[..2],
+            [
+                PathStep {
+                    x: 4,
+                    y: 6,
+                    direction: Direction::Up
+                },
+                PathStep {
+                    x: 4,
+                    y: 5,
+                    direction: Direction::Up
+                }
+            ]
+        );
+        // the guard turns at (4, 1) without moving
+        assert!(path.contains(&PathStep {
+            x: 4,
+            y: 1,
+            direction: Direction::Right
+        }));
+        assert_eq!(
+            path.last(),
+            Some(&PathStep {
+                x: 7,
+                y: 9,
+                direction: Direction::Down
+            })
+        );
+    }
+
+    #[test]
+    fn test_loop_obstacles() {
+        let map = Map::from_lines(&example());
+        let obstacles = map.loop_obstacles();
+        assert_eq!(
+            obstacles.iter().map(|o| (o.x, o.y)).collect::<Vec<_>>(),
+            vec![(3, 6), (6, 7), (7, 7), (1, 8), (3, 8), (7, 9)]
+        );
+        assert_eq!(
+            obstacles[0],
+            LoopObstacle {
+                x: 3,
+                y: 6,
+                cycle_length: 18
+            }
+        );
+    }
+
+    #[test]
+    fn test_render_path() {
+        let map = Map::from_lines(&example());
+        let path = map.path_with_obstacle(Some((3, 6)));
+        assert_eq!(
+            map.render_path(&path, Some((3, 6))),
+            "....#.....
+....+---+#
+....|...|.
+..#.|...|.
+....|..#|.
+....|...|.
+.#.O^---+.
+........#.
+#.........
+......#...
+"
+        );
+    }
+}
@@ -1,75 +1,610 @@
 use crate::util::aoc_read_day_lines;
-use memmap::Mmap;
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use std::fmt;
+use std::num::IntErrorKind;
 
-trait Concat {
-    fn concat(&self, other: usize) -> usize;
+// Calibration values are u128 so that targets too large for a u64 still fit.
+pub type Value = u128;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CalibrationError {
+    // no `target: operands` shape, or no operands at all
+    Malformed(String),
+    // a target or operand that is not a number
+    InvalidNumber(String),
+    // a target or operand that does not fit in a Value
+    TooLarge(String),
+    // the total calibration result does not fit in a Value
+    SumOverflow,
+}
+
+impl fmt::Display for CalibrationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CalibrationError::Malformed(line) => write!(f, "malformed equation: {:?}", line),
+            CalibrationError::InvalidNumber(s) => write!(f, "not a number: {:?}", s),
+            CalibrationError::TooLarge(s) => write!(f, "does not fit in a u128: {}", s),
+            CalibrationError::SumOverflow => write!(f, "total calibration result overflows"),
+        }
+    }
 }
 
-impl Concat for usize {
-    /// eg 12.concat(135) == 12135
-    fn concat(&self, other: usize) -> usize {
-        let num_digits = (other as f64).log10().floor() as usize + 1;
-        self * 10usize.pow(num_digits as u32) + other
+// Number of digits of `n` in the given base; 0 has one digit.
+fn num_digits(mut n: Value, base: Value) -> u32 {
+    let mut digits = 1;
+    while n >= base {
+        n /= base;
+        digits += 1;
     }
+    digits
+}
+
+// eg concat(12, 135, 10) == Some(12135), None on overflow
+fn concat(lhs: Value, rhs: Value, base: Value) -> Option<Value> {
+    let shift = base.checked_pow(num_digits(rhs, base))?;
+    lhs.checked_mul(shift)?.checked_add(rhs)
 }
 
-fn parse_line(target: usize, operands: &[usize], is_part2: bool) -> usize {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Add,
+    Mul,
+    // `||`, joining the decimal digits of both operands
+    Concat,
+    Sub,
+    // integer division, rounding down
+    Div,
+    Xor,
+    // like Concat, but joining digits in the given base
+    ConcatBase(u32),
+}
+
+impl Operator {
+    pub const PART1: [Operator; 2] = [Operator::Add, Operator::Mul];
+    pub const PART2: [Operator; 3] = [Operator::Add, Operator::Mul, Operator::Concat];
+
+    // None when the result is undefined, e.g. negative or division by zero.
+    pub fn apply(self, lhs: Value, rhs: Value) -> Option<Value> {
+        match self {
+            Operator::Add => lhs.checked_add(rhs),
+            Operator::Mul => lhs.checked_mul(rhs),
+            Operator::Concat => concat(lhs, rhs, 10),
+            Operator::Sub => lhs.checked_sub(rhs),
+            Operator::Div => lhs.checked_div(rhs),
+            Operator::Xor => Some(lhs ^ rhs),
+            Operator::ConcatBase(base) if base < 2 => None,
+            Operator::ConcatBase(base) => concat(lhs, rhs, base as Value),
+        }
+    }
+
+    pub fn symbol(self) -> String {
+        match self {
+            Operator::Add => "+".to_string(),
+            Operator::Mul => "*".to_string(),
+            Operator::Concat => "||".to_string(),
+            Operator::Sub => "-".to_string(),
+            Operator::Div => "/".to_string(),
+            Operator::Xor => "^".to_string(),
+            Operator::ConcatBase(base) => format!("||{}", base),
+        }
+    }
+
+    // The left operand that gives `result` when combined with `rhs`, if there is exactly one.
+    fn unapply(self, result: Value, rhs: Value) -> Option<Value> {
+        let lhs = match self {
+            Operator::Add => result.checked_sub(rhs)?,
+            Operator::Mul if rhs != 0 && result.is_multiple_of(rhs) => result / rhs,
+            Operator::Mul => return None,
+            Operator::Concat => Operator::ConcatBase(10).unapply(result, rhs)?,
+            Operator::Sub => result.checked_add(rhs)?,
+            Operator::Div => return None,
+            Operator::Xor => result ^ rhs,
+            Operator::ConcatBase(base) if base < 2 => return None,
+            Operator::ConcatBase(base) => {
+                // what lhs was multiplied by
+                let shift = (base as Value).checked_pow(num_digits(rhs, base as Value))?;
+                if result % shift != rhs {
+                    return None;
+                }
+                result / shift
+            }
+        };
+        (self.apply(lhs, rhs) == Some(result)).then_some(lhs)
+    }
+
+    // Whether every result has at most one left operand, so the search can run backwards from
+    // the target. Integer division does not, and neither does multiplication by zero.
+    fn is_invertible(self, rhs: Value) -> bool {
+        match self {
+            Operator::Div => false,
+            Operator::Mul => rhs != 0,
+            _ => true,
+        }
+    }
+
+    // Whether applying the operator never makes a positive running value smaller, which is what
+    // allows pruning branches that already overshoot the target.
+    fn never_decreases(self) -> bool {
+        matches!(
+            self,
+            Operator::Add | Operator::Mul | Operator::Concat | Operator::ConcatBase(_)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Solver {
+    // left to right from the first operand, pruning values above the target where possible
+    Forward,
+    // right to left from the target, undoing each operator: `+` by subtraction, `*` only when
+    // divisible and `||` only when the target ends with the operand's digits. Falls back to
+    // Forward when an operator cannot be undone unambiguously.
+    Reverse,
+}
+
+fn can_prune(operands: &[Value], operators: &[Operator]) -> bool {
+    operators.iter().all(|op| op.never_decreases()) && operands.iter().all(|&n| n > 0)
+}
+
+fn can_reverse(operands: &[Value], operators: &[Operator]) -> bool {
+    operands
+        .iter()
+        .skip(1)
+        .all(|&n| operators.iter().all(|op| op.is_invertible(n)))
+}
+
+fn resolve(solver: Solver, operands: &[Value], operators: &[Operator]) -> Solver {
+    match solver {
+        Solver::Reverse if !can_reverse(operands, operators) => Solver::Forward,
+        solver => solver,
+    }
+}
+
+pub fn is_solvable(
+    target: Value,
+    operands: &[Value],
+    operators: &[Operator],
+    solver: Solver,
+) -> bool {
+    if operands.is_empty() {
+        return false;
+    }
+    match resolve(solver, operands, operators) {
+        Solver::Forward => parse_line(target, operands, operators),
+        Solver::Reverse => parse_line_reverse(target, operands, operators),
+    }
+}
+
+fn parse_line_reverse(target: Value, operands: &[Value], operators: &[Operator]) -> bool {
+    let mut stack = Vec::with_capacity(100);
+    stack.push((target, operands.len() - 1));
+    while let Some((n, i)) = stack.pop() {
+        if i == 0 {
+            if n == operands[0] {
+                return true;
+            }
+            continue;
+        }
+        for op in operators {
+            if let Some(prev) = op.unapply(n, operands[i]) {
+                stack.push((prev, i - 1));
+            }
+        }
+    }
+    false
+}
+
+fn parse_line(target: Value, operands: &[Value], operators: &[Operator]) -> bool {
+    let prune = can_prune(operands, operators);
     let mut stack = Vec::with_capacity(100);
 
-    if operands.len() > 0 {
+    if !operands.is_empty() {
         stack.push((operands[0], 0));
     }
     while let Some((n, i)) = stack.pop() {
         let is_last = i == operands.len() - 1;
 
         if is_last && n == target {
-            return target;
+            return true;
         }
 
         if !is_last {
-            let addition = n + operands[i + 1];
-            if addition <= target {
-                stack.push((addition, i + 1));
-            }
-            let multiplication = n * operands[i + 1];
-            if multiplication <= target {
-                stack.push((multiplication, i + 1));
-            }
-            if is_part2 {
-                let concatenation = n.concat(operands[i + 1]);
-                if concatenation <= target {
-                    stack.push((concatenation, i + 1));
+            for op in operators {
+                if let Some(next) = op.apply(n, operands[i + 1]) {
+                    if !prune || next <= target {
+                        stack.push((next, i + 1));
+                    }
                 }
             }
         }
     }
 
-    0
+    false
 }
 
-pub fn day7_part1() -> usize {
-    let lines = aoc_read_day_lines(7);
-    lines
-        .par_iter()
-        .map(|line| {
-            let (target, operands) = line.split_once(": ").unwrap();
-            let target = target.parse().unwrap();
-            let operands: Vec<_> = operands.split(' ').map(|s| s.parse().unwrap()).collect();
-            parse_line(target, &operands, false)
+fn visit_solutions(
+    target: Value,
+    operands: &[Value],
+    operators: &[Operator],
+    prune: bool,
+    value: Value,
+    path: &mut Vec<Operator>,
+    on_solution: &mut impl FnMut(&[Operator]),
+) {
+    let i = path.len() + 1;
+    if i == operands.len() {
+        if value == target {
+            on_solution(path);
+        }
+        return;
+    }
+    for &op in operators {
+        let Some(next) = op.apply(value, operands[i]) else {
+            continue;
+        };
+        if prune && next > target {
+            continue;
+        }
+        path.push(op);
+        visit_solutions(target, operands, operators, prune, next, path, on_solution);
+        path.pop();
+    }
+}
+
+// Walks from the target back to the first operand, with `path` holding operators last to first.
+fn visit_solutions_reverse(
+    operands: &[Value],
+    operators: &[Operator],
+    value: Value,
+    path: &mut Vec<Operator>,
+    on_solution: &mut impl FnMut(&[Operator]),
+) {
+    let i = operands.len() - 1 - path.len();
+    if i == 0 {
+        if value == operands[0] {
+            let forward = path.iter().rev().copied().collect::<Vec<_>>();
+            on_solution(&forward);
+        }
+        return;
+    }
+    for &op in operators {
+        let Some(prev) = op.unapply(value, operands[i]) else {
+            continue;
+        };
+        path.push(op);
+        visit_solutions_reverse(operands, operators, prev, path, on_solution);
+        path.pop();
+    }
+}
+
+fn for_each_solution(
+    target: Value,
+    operands: &[Value],
+    operators: &[Operator],
+    solver: Solver,
+    on_solution: &mut impl FnMut(&[Operator]),
+) {
+    let Some(&first) = operands.first() else {
+        return;
+    };
+    let operators = distinct(operators);
+    let mut path = Vec::with_capacity(operands.len());
+    match resolve(solver, operands, &operators) {
+        Solver::Forward => {
+            let prune = can_prune(operands, &operators);
+            visit_solutions(
+                target,
+                operands,
+                &operators,
+                prune,
+                first,
+                &mut path,
+                on_solution,
+            );
+        }
+        Solver::Reverse => {
+            visit_solutions_reverse(operands, &operators, target, &mut path, on_solution);
+        }
+    }
+}
+
+fn distinct(operators: &[Operator]) -> Vec<Operator> {
+    let mut distinct: Vec<Operator> = Vec::with_capacity(operators.len());
+    for &op in operators {
+        if !distinct.contains(&op) {
+            distinct.push(op);
+        }
+    }
+    distinct
+}
+
+// Every operator sequence (applied left to right) that turns the operands into the target.
+pub fn solutions(
+    target: Value,
+    operands: &[Value],
+    operators: &[Operator],
+    solver: Solver,
+) -> Vec<Vec<Operator>> {
+    let mut found = Vec::new();
+    for_each_solution(target, operands, operators, solver, &mut |path| {
+        found.push(path.to_vec())
+    });
+    found
+}
+
+pub fn count_solutions(
+    target: Value,
+    operands: &[Value],
+    operators: &[Operator],
+    solver: Solver,
+) -> usize {
+    let mut count = 0;
+    for_each_solution(target, operands, operators, solver, &mut |_| count += 1);
+    count
+}
+
+// Renders a solution like `81 + 40 * 27`.
+pub fn format_solution(operands: &[Value], solution: &[Operator]) -> String {
+    let mut out = operands.first().map(|n| n.to_string()).unwrap_or_default();
+    for (op, n) in solution.iter().zip(operands.iter().skip(1)) {
+        out.push_str(&format!(" {} {}", op.symbol(), n));
+    }
+    out
+}
+
+fn parse_value(s: &str) -> Result<Value, CalibrationError> {
+    s.parse()
+        .map_err(|e: std::num::ParseIntError| match e.kind() {
+            IntErrorKind::PosOverflow => CalibrationError::TooLarge(s.to_string()),
+            _ => CalibrationError::InvalidNumber(s.to_string()),
         })
-        .sum()
 }
 
-pub fn day7_part2() -> usize {
-    let lines = aoc_read_day_lines(7);
+pub fn parse_calibration(line: &str) -> Result<(Value, Vec<Value>), CalibrationError> {
+    let (target, operands) = line
+        .split_once(": ")
+        .ok_or_else(|| CalibrationError::Malformed(line.to_string()))?;
+    let target = parse_value(target)?;
+    let operands = operands
+        .split(' ')
+        .filter(|s| !s.is_empty())
+        .map(parse_value)
+        .collect::<Result<Vec<_>, _>>()?;
+    if operands.is_empty() {
+        return Err(CalibrationError::Malformed(line.to_string()));
+    }
+    Ok((target, operands))
+}
+
+pub fn day7(
+    test_input: Option<Vec<String>>,
+    operators: &[Operator],
+    solver: Solver,
+) -> Result<Value, CalibrationError> {
+    let lines = test_input.unwrap_or_else(|| aoc_read_day_lines(7));
     lines
         .par_iter()
         .map(|line| {
-            let (target, operands) = line.split_once(": ").unwrap();
-            let target = target.parse().unwrap();
-            let operands: Vec<_> = operands.split(' ').map(|s| s.parse().unwrap()).collect();
-            parse_line(target, &operands, true)
+            let (target, operands) = parse_calibration(line)?;
+            if is_solvable(target, &operands, operators, solver) {
+                Ok(target)
+            } else {
+                Ok(0)
+            }
         })
-        .sum()
+        .try_reduce(
+            || 0,
+            |a, b| a.checked_add(b).ok_or(CalibrationError::SumOverflow),
+        )
+}
+
+pub fn day7_part1(test_input: Option<Vec<String>>) -> Value {
+    day7(test_input, &Operator::PART1, Solver::Reverse).unwrap_or_else(|e| panic!("{}", e))
+}
+
+pub fn day7_part2(test_input: Option<Vec<String>>) -> Value {
+    day7(test_input, &Operator::PART2, Solver::Reverse).unwrap_or_else(|e| panic!("{}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "190: 10 19
+3267: 81 40 27
+83: 17 5
+156: 15 6
+7290: 6 8 6 15
+161011: 16 10 13
+192: 17 8 14
+21037: 9 7 18 13
+292: 11 6 16 20";
+
+    fn example() -> Vec<String> {
+        EXAMPLE.lines().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_day7_part1() {
+        assert_eq!(day7_part1(Some(example())), 3749);
+    }
+
+    #[test]
+    fn test_day7_part2() {
+        assert_eq!(day7_part2(Some(example())), 11387);
+    }
+
+    #[test]
+    fn test_solutions() {
+        let found = solutions(3267, &[81, 40, 27], &Operator::PART1, Solver::Forward);
+        assert_eq!(
+            found,
+            vec![
+                vec![Operator::Add, Operator::Mul],
+                vec![Operator::Mul, Operator::Add]
+            ]
+        );
+        assert_eq!(format_solution(&[81, 40, 27], &found[1]), "81 * 40 + 27");
+        assert_eq!(
+            count_solutions(7290, &[6, 8, 6, 15], &Operator::PART2, Solver::Forward),
+            1
+        );
+        assert_eq!(
+            count_solutions(83, &[17, 5], &Operator::PART2, Solver::Forward),
+            0
+        );
+    }
+
+    #[test]
+    fn test_extra_operators() {
+        // 10 - 4 = 6, 6 / 3 = 2
+        assert_eq!(
+            solutions(
+                2,
+                &[10, 4, 3],
+                &[Operator::Sub, Operator::Div],
+                Solver::Reverse
+            ),
+            vec![vec![Operator::Sub, Operator::Div]]
+        );
+        // 5 ^ 3 = 6
+        assert_eq!(
+            count_solutions(6, &[5, 3], &[Operator::Xor], Solver::Reverse),
+            1
+        );
+        assert_eq!(Operator::Sub.apply(3, 4), None);
+        assert_eq!(Operator::Div.apply(3, 0), None);
+        // 0b10 joined with 0b11 in base 2 is 0b1011
+        assert_eq!(Operator::ConcatBase(2).apply(2, 3), Some(11));
+        assert_eq!(Operator::ConcatBase(10).apply(12, 345), Some(12345));
+        // duplicate operators do not produce duplicate solutions
+        assert_eq!(
+            count_solutions(
+                4,
+                &[2, 2],
+                &[Operator::Add, Operator::Add, Operator::Mul],
+                Solver::Forward
+            ),
+            2
+        );
+    }
+
+    #[test]
+    fn test_solvers_agree() {
+        let operator_sets: [&[Operator]; 4] = [
+            &Operator::PART1,
+            &Operator::PART2,
+            &[
+                Operator::Add,
+                Operator::Sub,
+                Operator::Xor,
+                Operator::ConcatBase(3),
+            ],
+            &[Operator::Mul, Operator::Div, Operator::Add],
+        ];
+        for operators in operator_sets {
+            for line in example() {
+                let (target, operands) = parse_calibration(&line).unwrap();
+                let mut forward = solutions(target, &operands, operators, Solver::Forward);
+                let mut reverse = solutions(target, &operands, operators, Solver::Reverse);
+                forward.sort_by_key(|s| format!("{:?}", s));
+                reverse.sort_by_key(|s| format!("{:?}", s));
+                assert_eq!(forward, reverse, "{} with {:?}", line, operators);
+                assert_eq!(
+                    is_solvable(target, &operands, operators, Solver::Forward),
+                    is_solvable(target, &operands, operators, Solver::Reverse)
+                );
+            }
+        }
+        assert_eq!(
+            day7(Some(example()), &Operator::PART2, Solver::Forward),
+            day7(Some(example()), &Operator::PART2, Solver::Reverse)
+        );
+    }
+
+    #[test]
+    fn test_unapply() {
+        assert_eq!(Operator::Concat.unapply(156, 6), Some(15));
+        assert_eq!(Operator::Concat.unapply(156, 56), Some(1));
+        assert_eq!(Operator::Concat.unapply(156, 7), None);
+        assert_eq!(Operator::Mul.unapply(156, 7), None);
+        assert_eq!(Operator::Mul.unapply(156, 6), Some(26));
+        assert_eq!(Operator::Add.unapply(5, 6), None);
+        // a zero operand makes multiplication ambiguous, so Reverse falls back to Forward
+        assert!(is_solvable(0, &[7, 0], &Operator::PART1, Solver::Reverse));
+    }
+
+    #[test]
+    fn test_concat_edge_values() {
+        assert_eq!(num_digits(0, 10), 1);
+        assert_eq!(num_digits(10, 10), 2);
+        assert_eq!(num_digits(999, 10), 3);
+        assert_eq!(num_digits(1000, 10), 4);
+        assert_eq!(Operator::Concat.apply(12, 0), Some(120));
+        assert_eq!(Operator::Concat.apply(12, 10), Some(1210));
+        assert_eq!(Operator::Concat.apply(12, 999), Some(12999));
+        assert_eq!(Operator::Concat.apply(12, 1000), Some(121000));
+        assert_eq!(Operator::Concat.apply(0, 5), Some(5));
+        assert_eq!(Operator::Concat.unapply(120, 0), Some(12));
+        assert_eq!(Operator::Concat.unapply(121000, 1000), Some(12));
+        assert_eq!(Operator::Concat.unapply(12999, 999), Some(12));
+        assert_eq!(Operator::Concat.unapply(1210, 10), Some(12));
+    }
+
+    #[test]
+    fn test_overflow() {
+        assert_eq!(Operator::Mul.apply(Value::MAX, 2), None);
+        assert_eq!(Operator::Concat.apply(Value::MAX / 10, 99), None);
+        assert_eq!(Operator::ConcatBase(2).apply(1, Value::MAX), None);
+        // a target past u64::MAX is still solvable
+        let line = "36893488147419103230: 18446744073709551615 2";
+        let (target, operands) = parse_calibration(line).unwrap();
+        assert!(is_solvable(
+            target,
+            &operands,
+            &Operator::PART1,
+            Solver::Forward
+        ));
+        assert!(is_solvable(
+            target,
+            &operands,
+            &Operator::PART1,
+            Solver::Reverse
+        ));
+        assert_eq!(day7_part1(Some(vec![line.to_string()])), target);
+        // branches that overflow are dropped rather than wrapping around to the target
+        let big = Value::MAX.to_string();
+        let line = format!("{}: {} {} 1", big, big, big);
+        assert_eq!(day7_part2(Some(vec![line])), 0);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let too_large = "340282366920938463463374607431768211456";
+        assert_eq!(
+            parse_calibration(&format!("10: 5 {}", too_large)),
+            Err(CalibrationError::TooLarge(too_large.to_string()))
+        );
+        assert_eq!(
+            parse_calibration("10: 5 x"),
+            Err(CalibrationError::InvalidNumber("x".to_string()))
+        );
+        assert_eq!(
+            parse_calibration("10 5 5"),
+            Err(CalibrationError::Malformed("10 5 5".to_string()))
+        );
+        assert_eq!(
+            parse_calibration("10: "),
+            Err(CalibrationError::Malformed("10: ".to_string()))
+        );
+        let total = format!("{}: {}", Value::MAX, Value::MAX);
+        assert_eq!(
+            day7(
+                Some(vec![total.clone(), total]),
+                &Operator::PART1,
+                Solver::Forward
+            ),
+            Err(CalibrationError::SumOverflow)
+        );
+    }
 }
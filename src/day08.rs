@@ -1,71 +1,247 @@
 use crate::util::*;
-use smallvec::{smallvec, SmallVec};
+use std::collections::BTreeMap;
 
-const SIDE_LENGTH: usize = 50;
+// Which points along the line through two antennas of the same frequency are antinodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resonance {
+    // For antennas a and b, the points a - k*(b - a) and b + k*(b - a) for every k in the range.
+    // Part 1 is k = 1; k = 0 makes the antennas themselves antinodes.
+    Range { min_k: usize, max_k: usize },
+    // Every grid point exactly in line with the two antennas, stepping by (b - a) reduced by the
+    // gcd of its components so that no point in between is skipped.
+    Collinear,
+}
 
-fn is_in_bounds((x, y): (isize, isize)) -> bool {
-    x < SIDE_LENGTH as isize && y < SIDE_LENGTH as isize && x >= 0 && y >= 0
+fn gcd(a: isize, b: isize) -> isize {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
 }
 
-pub fn day8(min_k: usize, max_k: usize) -> usize {
-    let bytes = aoc_read_day_bytes(8);
-    // 'z' ascii code is 122, '0' ascii code is 48
-    let mut nodes: [SmallVec<[usize; 8]>; 122 - 48 + 1] = std::array::from_fn(|_| smallvec![]);
-    let mut i = 0;
-    for byte in bytes {
-        if (byte as usize) < 48 {
-            i += (byte == b'.') as usize;
-            continue;
+pub struct AntennaMap {
+    pub width: usize,
+    pub height: usize,
+    // antenna positions as (x, y) in reading order, keyed by frequency
+    pub antennas: BTreeMap<char, Vec<(usize, usize)>>,
+}
+
+impl AntennaMap {
+    // Any character other than '.' and '#' is an antenna of that frequency.
+    pub fn from_lines(lines: &[String]) -> Self {
+        let lines = lines
+            .iter()
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>();
+        let height = lines.len();
+        let width = lines.first().map_or(0, |line| line.chars().count());
+        assert!(
+            lines.iter().all(|line| line.chars().count() == width),
+            "map rows must all have the same width"
+        );
+        let mut antennas: BTreeMap<char, Vec<(usize, usize)>> = BTreeMap::new();
+        for (y, line) in lines.iter().enumerate() {
+            for (x, c) in line.chars().enumerate() {
+                if c != '.' && c != '#' {
+                    antennas.entry(c).or_default().push((x, y));
+                }
+            }
+        }
+        AntennaMap {
+            width,
+            height,
+            antennas,
+        }
+    }
+
+    fn in_bounds(&self, (x, y): (isize, isize)) -> Option<(usize, usize)> {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            Some((x as usize, y as usize))
+        } else {
+            None
         }
-        nodes[byte as usize - 48].push(i);
-        i += 1;
     }
-    let mut antinodes: [bool; SIDE_LENGTH * SIDE_LENGTH] = [false; SIDE_LENGTH * SIDE_LENGTH];
-    let mut antinodes_count = 0;
-    for positions in nodes.iter().filter(|positions| !positions.is_empty()) {
-        for i in 0..positions.len() - 1 {
-            for j in i + 1..positions.len() {
-                let x1 = positions[i] % SIDE_LENGTH;
-                let y1 = positions[i] / SIDE_LENGTH;
-                let x2 = positions[j] % SIDE_LENGTH;
-                let y2 = positions[j] / SIDE_LENGTH;
-                let xdiff = x2 as isize - x1 as isize;
-                let ydiff = y2 as isize - y1 as isize;
+
+    fn pair_antinodes(
+        &self,
+        (x1, y1): (usize, usize),
+        (x2, y2): (usize, usize),
+        resonance: Resonance,
+        out: &mut Vec<(usize, usize)>,
+    ) {
+        let (x1, y1, x2, y2) = (x1 as isize, y1 as isize, x2 as isize, y2 as isize);
+        let (xdiff, ydiff) = (x2 - x1, y2 - y1);
+        match resonance {
+            Resonance::Range { min_k, max_k } => {
                 for k in min_k..=max_k {
-                    let antinode1 = (
-                        x1 as isize - xdiff * k as isize,
-                        y1 as isize - ydiff * k as isize,
-                    );
-                    let antinode2 = (
-                        x2 as isize + xdiff * k as isize,
-                        y2 as isize + ydiff * k as isize,
-                    );
-                    let first_in_bounds = is_in_bounds(antinode1);
-                    let second_in_bounds = is_in_bounds(antinode2);
-                    if !first_in_bounds && !second_in_bounds {
+                    let k = k as isize;
+                    let antinode1 = self.in_bounds((x1 - xdiff * k, y1 - ydiff * k));
+                    let antinode2 = self.in_bounds((x2 + xdiff * k, y2 + ydiff * k));
+                    if antinode1.is_none() && antinode2.is_none() {
                         break;
                     }
-                    if first_in_bounds {
-                        let idx = antinode1.0 as usize + antinode1.1 as usize * SIDE_LENGTH;
-                        antinodes_count += !antinodes[idx] as usize;
-                        antinodes[idx] = true;
+                    out.extend(antinode1);
+                    out.extend(antinode2);
+                }
+            }
+            Resonance::Collinear => {
+                let divisor = gcd(xdiff, ydiff);
+                let (dx, dy) = (xdiff / divisor, ydiff / divisor);
+                for (dx, dy) in [(dx, dy), (-dx, -dy)] {
+                    let mut pos = (x1, y1);
+                    while let Some(antinode) = self.in_bounds(pos) {
+                        out.push(antinode);
+                        pos = (pos.0 + dx, pos.1 + dy);
                     }
-                    if second_in_bounds {
-                        let idx = antinode2.0 as usize + antinode2.1 as usize * SIDE_LENGTH;
-                        antinodes_count += !antinodes[idx] as usize;
-                        antinodes[idx] = true;
+                }
+            }
+        }
+    }
+
+    // Antinode positions as (x, y) in reading order, grouped by the frequency that creates them.
+    // The same position can appear under several frequencies.
+    pub fn antinodes(&self, resonance: Resonance) -> BTreeMap<char, Vec<(usize, usize)>> {
+        self.antennas
+            .iter()
+            .map(|(&frequency, positions)| {
+                let mut antinodes = Vec::new();
+                for (i, &a) in positions.iter().enumerate() {
+                    for &b in positions[i + 1..].iter() {
+                        self.pair_antinodes(a, b, resonance, &mut antinodes);
                     }
                 }
+                antinodes.sort_unstable_by_key(|&(x, y)| (y, x));
+                antinodes.dedup();
+                (frequency, antinodes)
+            })
+            .collect()
+    }
+
+    // Number of distinct positions that are an antinode of any frequency.
+    pub fn count_antinodes(&self, resonance: Resonance) -> usize {
+        let mut seen = vec![false; self.width * self.height];
+        let mut count = 0;
+        for antinodes in self.antinodes(resonance).values() {
+            for &(x, y) in antinodes {
+                count += !seen[y * self.width + x] as usize;
+                seen[y * self.width + x] = true;
             }
         }
+        count
     }
-    antinodes_count
 }
 
-pub fn day8_part1() -> usize {
-    day8(1, 1)
+pub fn day8(test_input: Option<Vec<String>>, resonance: Resonance) -> usize {
+    let lines = test_input.unwrap_or_else(|| aoc_read_day_lines(8));
+    AntennaMap::from_lines(&lines).count_antinodes(resonance)
+}
+
+pub fn day8_part1(test_input: Option<Vec<String>>) -> usize {
+    day8(test_input, Resonance::Range { min_k: 1, max_k: 1 })
+}
+
+pub fn day8_part2(test_input: Option<Vec<String>>) -> usize {
+    day8(test_input, Resonance::Collinear)
 }
 
-pub fn day8_part2() -> usize {
-    day8(0, usize::MAX)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "............
+........0...
+.....0......
+.......0....
+....0.......
+......A.....
+............
+............
+........A...
+.........A..
+............
+............";
+
+    fn example() -> Vec<String> {
+        EXAMPLE.lines().map(|s| s.to_string()).collect()
+    }
+
+    fn lines(s: &str) -> Vec<String> {
+        s.lines().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_day8_part1() {
+        assert_eq!(day8_part1(Some(example())), 14);
+    }
+
+    #[test]
+    fn test_day8_part2() {
+        assert_eq!(day8_part2(Some(example())), 34);
+        // for this map the unreduced range model finds the same points
+        assert_eq!(
+            day8(
+                Some(example()),
+                Resonance::Range {
+                    min_k: 0,
+                    max_k: usize::MAX
+                }
+            ),
+            34
+        );
+    }
+
+    #[test]
+    fn test_antinodes_by_frequency() {
+        let map = AntennaMap::from_lines(&lines(
+            "..........
+...#......
+..........
+....a.....
+..........
+.....a....
+..........
+......#...
+..........
+..........",
+        ));
+        assert_eq!(map.antennas.len(), 1);
+        let antinodes = map.antinodes(Resonance::Range { min_k: 1, max_k: 1 });
+        assert_eq!(antinodes[&'a'], vec![(3, 1), (6, 7)]);
+    }
+
+    #[test]
+    fn test_non_square_map_and_collinear_steps() {
+        // the antennas are two columns apart, so the collinear model also hits the column between
+        let map = AntennaMap::from_lines(&lines(
+            "........
+.ä.ä....
+Ω.......",
+        ));
+        assert_eq!((map.width, map.height), (8, 3));
+        let antinodes = map.antinodes(Resonance::Collinear);
+        assert_eq!(
+            antinodes[&'ä'],
+            vec![
+                (0, 1),
+                (1, 1),
+                (2, 1),
+                (3, 1),
+                (4, 1),
+                (5, 1),
+                (6, 1),
+                (7, 1)
+            ]
+        );
+        assert_eq!(
+            map.antinodes(Resonance::Range {
+                min_k: 0,
+                max_k: usize::MAX
+            })[&'ä'],
+            vec![(1, 1), (3, 1), (5, 1), (7, 1)]
+        );
+        // a lone antenna has no antinodes
+        assert!(antinodes[&'Ω'].is_empty());
+        assert_eq!(map.count_antinodes(Resonance::Collinear), 8);
+    }
 }
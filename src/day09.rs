@@ -1,134 +1,808 @@
 use crate::util::*;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::fmt;
 
-pub fn day9_part1() -> usize {
-    let mut input = aoc_read_day_bytes(9)
-        .iter()
-        .map(|&c| (c - b'0') as usize)
-        .collect::<Vec<usize>>();
-
-    // input has odd length so the first free block is at index 1 and the first file from end is at index len - 1
-    let mut l = 0;
-    let mut r = input.len() - 1;
-    let mut compacted_disk = vec![];
-    let mut fileid_l = 0;
-    let mut fileid_r = input.len() / 2;
-    while l <= r {
-        if l % 2 == 0 {
-            for _ in 0..input[l] {
-                compacted_disk.push(fileid_l);
-            }
-            l += 1;
-            fileid_l += 1;
-        } else {
-            let freespace = input[l];
-            let space_to_take = input[r].min(input[l]);
-            for _ in 0..space_to_take {
-                compacted_disk.push(fileid_r);
-            }
-            if freespace > input[r] {
-                input[l] -= input[r];
-                input[r] = 0;
-                r -= 2;
-                fileid_r -= 1;
-            } else {
-                input[l] -= freespace;
-                input[r] -= freespace;
-                l += 1;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    // move single blocks from the end of the disk into the leftmost free block (part 1)
+    BlockLevel,
+    // move whole files, highest id first, into the leftmost free span that fits (part 2)
+    FirstFit,
+    // like FirstFit, but into the smallest free span that fits, leftmost on ties
+    BestFit,
+    // pack every file whole at the start of the disk in disk order, leaving all free space at
+    // the end
+    DefragmentToEnd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    pub file_id: Option<usize>,
+    pub run_length: usize,
+}
+
+impl Block {
+    fn is_empty(&self) -> bool {
+        self.file_id.is_none()
+    }
+}
+
+// `len` blocks of a file moved from `from` to `to`, both block positions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Move {
+    pub file_id: usize,
+    pub from: usize,
+    pub to: usize,
+    pub len: usize,
+}
+
+// A run of positions `start..start + len` that hold `before` in one layout and `after` in the
+// other, None meaning free.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LayoutChange {
+    pub start: usize,
+    pub len: usize,
+    pub before: Option<usize>,
+    pub after: Option<usize>,
+}
+
+// A run of blocks belonging to one file.
+#[derive(Debug, Clone, Copy)]
+struct Extent {
+    file_id: usize,
+    start: usize,
+    len: usize,
+}
+
+// The free spans of a disk being compacted, always merged with their free neighbours, plus a
+// heap of span starts per length for finding one that fits. Spans at least `max_len` long share
+// the last heap. Heap entries go stale when their span is taken or merged into another one, and
+// are dropped once they come up.
+struct FreeSpans {
+    // start -> len
+    spans: BTreeMap<usize, usize>,
+    // Reverse((rank, start, len)), the rank being the length for best fit and 0 for first fit
+    by_len: Vec<BinaryHeap<Reverse<(usize, usize, usize)>>>,
+    best_fit: bool,
+}
+
+impl FreeSpans {
+    fn new(spans: Vec<(usize, usize)>, max_len: usize, best_fit: bool) -> Self {
+        let mut free = FreeSpans {
+            spans: BTreeMap::new(),
+            by_len: vec![BinaryHeap::new(); max_len + 1],
+            best_fit,
+        };
+        for (start, len) in spans {
+            free.insert(start, len);
+        }
+        free
+    }
+
+    fn insert(&mut self, start: usize, len: usize) {
+        self.spans.insert(start, len);
+        let rank = if self.best_fit { len } else { 0 };
+        let bucket = len.min(self.by_len.len() - 1);
+        self.by_len[bucket].push(Reverse((rank, start, len)));
+    }
+
+    // The smallest entry of a heap that is still a span.
+    fn top(&mut self, bucket: usize) -> Option<(usize, usize)> {
+        while let Some(&Reverse((_, start, len))) = self.by_len[bucket].peek() {
+            if self.spans.get(&start) == Some(&len) {
+                return Some((start, len));
             }
+            self.by_len[bucket].pop();
         }
+        None
     }
 
-    let mut sum = 0;
-    for (pos, fileid) in compacted_disk.iter().enumerate() {
-        sum += pos * fileid;
+    // (start, len) of the span a file of `len` blocks at `before` moves into, if any: the leftmost
+    // one that fits, or with best fit the smallest, leftmost on ties. It has to start left of the
+    // file.
+    fn find(&mut self, len: usize, before: usize) -> Option<(usize, usize)> {
+        let last = self.by_len.len() - 1;
+        let mut chosen: Option<(usize, usize)> = None;
+        for bucket in len..=last {
+            let Some((start, span_len)) = self.top(bucket).filter(|&(start, _)| start < before)
+            else {
+                if self.best_fit && bucket == last {
+                    // the smallest long span may lie past the file while a bigger one does not
+                    return self
+                        .spans
+                        .range(..before)
+                        .filter(|&(_, &span_len)| span_len >= last)
+                        .min_by_key(|&(&start, &span_len)| (span_len, start))
+                        .map(|(&start, &span_len)| (start, span_len));
+                }
+                continue;
+            };
+            if self.best_fit {
+                return Some((start, span_len));
+            }
+            if chosen.is_none_or(|(chosen_start, _)| start < chosen_start) {
+                chosen = Some((start, span_len));
+            }
+        }
+        chosen
+    }
+
+    // Uses the first `len` blocks of the span at `start`.
+    fn take(&mut self, start: usize, len: usize) {
+        let span_len = self.spans.remove(&start).expect("not a free span");
+        if span_len > len {
+            self.insert(start + len, span_len - len);
+        }
     }
-    sum
-}
 
-#[derive(Clone, Copy)]
+    // Frees `start..start + len`, merging it with the free spans on either side.
+    fn release(&mut self, mut start: usize, mut len: usize) {
+        if let Some((&prev_start, &prev_len)) = self.spans.range(..start).next_back() {
+            if prev_start + prev_len == start {
+                self.spans.remove(&prev_start);
+                start = prev_start;
+                len += prev_len;
+            }
+        }
+        if let Some(next_len) = self.spans.remove(&(start + len)) {
+            len += next_len;
+        }
+        self.insert(start, len);
+    }
+}
 
-struct Block {
-    file_id: Option<usize>,
-    run_length: u8,
+// The disk as runs of blocks in disk order. Adjacent runs never share a file id (or are both
+// free) and there are no empty runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiskMap {
+    pub blocks: Vec<Block>,
 }
 
-impl Block {
-    fn is_empty(&self) -> bool {
-        self.file_id.is_none()
+impl DiskMap {
+    // The dense format: digits alternating between file and free-space lengths. Anything that is
+    // not a digit (eg a trailing newline) is ignored.
+    pub fn parse(bytes: &[u8]) -> Self {
+        let blocks = bytes
+            .iter()
+            .filter(|c| c.is_ascii_digit())
+            .enumerate()
+            .map(|(idx, &c)| Block {
+                file_id: if idx % 2 == 0 { Some(idx / 2) } else { None },
+                run_length: (c - b'0') as usize,
+            });
+        Self::from_blocks(blocks)
     }
 
-    fn can_fit(&self, other: &Block) -> bool {
-        self.is_empty() && other.run_length <= self.run_length
+    fn from_blocks(blocks: impl IntoIterator<Item = Block>) -> Self {
+        let mut merged: Vec<Block> = Vec::new();
+        for block in blocks {
+            if block.run_length == 0 {
+                continue;
+            }
+            match merged.last_mut() {
+                Some(last) if last.file_id == block.file_id => last.run_length += block.run_length,
+                _ => merged.push(block),
+            }
+        }
+        DiskMap { blocks: merged }
+    }
+
+    // Lays out the extents with free space in between, padded with free space up to `size`.
+    fn from_extents(size: usize, mut extents: Vec<Extent>) -> Self {
+        extents.sort_unstable_by_key(|e| e.start);
+        let mut blocks = Vec::with_capacity(extents.len() * 2 + 1);
+        let mut pos = 0;
+        for e in extents {
+            blocks.push(Block {
+                file_id: None,
+                run_length: e.start - pos,
+            });
+            blocks.push(Block {
+                file_id: Some(e.file_id),
+                run_length: e.len,
+            });
+            pos = e.start + e.len;
+        }
+        blocks.push(Block {
+            file_id: None,
+            run_length: size - pos,
+        });
+        Self::from_blocks(blocks)
     }
-}
 
-pub fn day9_part2() -> usize {
-    let mut compacted_disk = aoc_read_day_bytes(9)
-        .iter()
-        .map(|&c| (c - b'0') as u8)
-        .enumerate()
-        .map(|(idx, count)| Block {
-            file_id: if idx % 2 == 0 { Some(idx / 2) } else { None },
-            run_length: count,
+    // Total number of blocks, used or free.
+    pub fn size(&self) -> usize {
+        self.blocks.iter().map(|b| b.run_length).sum()
+    }
+
+    // (start, block) for every run.
+    fn runs(&self) -> impl Iterator<Item = (usize, Block)> + '_ {
+        self.blocks.iter().scan(0, |pos, &block| {
+            let start = *pos;
+            *pos += block.run_length;
+            Some((start, block))
         })
-        .collect::<Vec<Block>>();
-
-    let mut cur_fileid_to_move = compacted_disk.len() / 2;
-    let mut cur_fileidx_to_move = compacted_disk.len() - 1;
-    while cur_fileid_to_move > 0 {
-        let file_to_move = compacted_disk[cur_fileidx_to_move];
-        if file_to_move.file_id == Some(cur_fileid_to_move) {
-            for idx in 0..cur_fileidx_to_move {
-                let target_candidate_block = &mut compacted_disk[idx];
-                if target_candidate_block.can_fit(&file_to_move) {
-                    let leftover_space =
-                        target_candidate_block.run_length - file_to_move.run_length;
-                    target_candidate_block.file_id = file_to_move.file_id;
-                    target_candidate_block.run_length = file_to_move.run_length;
-                    compacted_disk[cur_fileidx_to_move] = Block {
-                        file_id: None,
-                        run_length: file_to_move.run_length,
-                    };
-                    if leftover_space == 0 {
-                        break;
-                    }
-                    let next_elem = &mut compacted_disk[idx + 1];
-                    if next_elem.is_empty() {
-                        // compact/modify
-                        next_elem.run_length += leftover_space;
-                    } else {
-                        compacted_disk.insert(
-                            idx + 1,
-                            Block {
-                                file_id: None,
-                                run_length: leftover_space,
-                            },
-                        );
-                        cur_fileidx_to_move += 1;
-                    }
-
-                    break;
+    }
+
+    fn extents(&self) -> Vec<Extent> {
+        self.runs()
+            .filter_map(|(start, block)| {
+                block.file_id.map(|file_id| Extent {
+                    file_id,
+                    start,
+                    len: block.run_length,
+                })
+            })
+            .collect()
+    }
+
+    // (start, len) of every free span in disk order.
+    fn free_spans(&self) -> Vec<(usize, usize)> {
+        self.runs()
+            .filter(|(_, block)| block.is_empty())
+            .map(|(start, block)| (start, block.run_length))
+            .collect()
+    }
+
+    // Sum of position * file id over every used block. Only depends on the layout, not on the
+    // strategy that produced it.
+    pub fn checksum(&self) -> usize {
+        self.runs()
+            .map(|(start, block)| {
+                let len = block.run_length;
+                // start + (start + 1) + ... + (start + len - 1)
+                block.file_id.unwrap_or(0) * (start * len + len * (len.saturating_sub(1)) / 2)
+            })
+            .sum()
+    }
+
+    pub fn compact(&self, strategy: Strategy) -> DiskMap {
+        self.compact_with_log(strategy).0
+    }
+
+    // The compacted disk along with every move made, in the order they were made.
+    pub fn compact_with_log(&self, strategy: Strategy) -> (DiskMap, Vec<Move>) {
+        let mut log = Vec::new();
+        let extents = match strategy {
+            Strategy::BlockLevel => self.compact_blocks(&mut log),
+            Strategy::FirstFit => self.compact_files(false, &mut log),
+            Strategy::BestFit => self.compact_files(true, &mut log),
+            Strategy::DefragmentToEnd => self.defragment(&mut log),
+        };
+        (Self::from_extents(self.size(), extents), log)
+    }
+
+    // File id per block, None for free blocks.
+    pub fn cells(&self) -> Vec<Option<usize>> {
+        self.blocks
+            .iter()
+            .flat_map(|b| std::iter::repeat_n(b.file_id, b.run_length))
+            .collect()
+    }
+
+    // Replays one entry of a move log, touching only the runs around its source and target.
+    // Panics if the move does not fit the current layout.
+    pub fn apply_move(&mut self, m: &Move) {
+        assert!(
+            self.fill(m.from, m.len, Some(m.file_id), None),
+            "{:?}: source",
+            m
+        );
+        assert!(
+            self.fill(m.to, m.len, None, Some(m.file_id)),
+            "{:?}: target is not free",
+            m
+        );
+    }
+
+    // Sets positions start..start + len from `expected` to `file_id`, splitting the runs at
+    // either end and merging the result with its neighbours. Returns false, changing nothing, if
+    // any of the positions does not hold `expected` or lies past the end of the disk.
+    fn fill(
+        &mut self,
+        start: usize,
+        len: usize,
+        expected: Option<usize>,
+        file_id: Option<usize>,
+    ) -> bool {
+        if len == 0 {
+            return true;
+        }
+        let end = start + len;
+        // index and start of the first run in the range, index and end of the last one
+        let mut first = None;
+        let mut last = None;
+        let mut pos = 0;
+        for (i, b) in self.blocks.iter().enumerate() {
+            let run_end = pos + b.run_length;
+            if first.is_none() && run_end > start {
+                first = Some((i, pos));
+            }
+            if run_end >= end {
+                last = Some((i, run_end));
+                break;
+            }
+            pos = run_end;
+        }
+        let (Some((i, run_start)), Some((j, run_end))) = (first, last) else {
+            return false;
+        };
+        if self.blocks[i..=j].iter().any(|b| b.file_id != expected) {
+            return false;
+        }
+        let lo = i.saturating_sub(1);
+        let hi = (j + 2).min(self.blocks.len());
+        let replaced = self.blocks[lo..i]
+            .iter()
+            .copied()
+            .chain([
+                Block {
+                    file_id: expected,
+                    run_length: start - run_start,
+                },
+                Block {
+                    file_id,
+                    run_length: len,
+                },
+                Block {
+                    file_id: expected,
+                    run_length: run_end - end,
+                },
+            ])
+            .chain(self.blocks[j + 1..hi].iter().copied());
+        let merged = Self::from_blocks(replaced.collect::<Vec<_>>()).blocks;
+        self.blocks.splice(lo..hi, merged);
+        true
+    }
+
+    // Runs of positions whose contents differ between the two layouts. A disk shorter than the
+    // other counts as free past its end.
+    pub fn diff(&self, other: &DiskMap) -> Vec<LayoutChange> {
+        let (before, after) = (self.cells(), other.cells());
+        let mut changes: Vec<LayoutChange> = Vec::new();
+        for pos in 0..before.len().max(after.len()) {
+            let b = before.get(pos).copied().flatten();
+            let a = after.get(pos).copied().flatten();
+            if a == b {
+                continue;
+            }
+            match changes.last_mut() {
+                Some(c) if c.start + c.len == pos && c.before == b && c.after == a => c.len += 1,
+                _ => changes.push(LayoutChange {
+                    start: pos,
+                    len: 1,
+                    before: b,
+                    after: a,
+                }),
+            }
+        }
+        changes
+    }
+
+    fn compact_blocks(&self, log: &mut Vec<Move>) -> Vec<Extent> {
+        let mut extents = self.extents();
+        let mut free = self.free_spans();
+        let mut moved = Vec::new();
+        let mut f = 0;
+        // extents[..r] are the ones not yet fully moved
+        let mut r = extents.len();
+        while f < free.len() && r > 0 {
+            let (free_start, free_len) = free[f];
+            let last = &mut extents[r - 1];
+            if free_start >= last.start {
+                break;
+            }
+            let n = free_len.min(last.len);
+            log.push(Move {
+                file_id: last.file_id,
+                from: last.start + last.len - n,
+                to: free_start,
+                len: n,
+            });
+            moved.push(Extent {
+                file_id: last.file_id,
+                start: free_start,
+                len: n,
+            });
+            last.len -= n;
+            if last.len == 0 {
+                r -= 1;
+            }
+            free[f] = (free_start + n, free_len - n);
+            if free_len == n {
+                f += 1;
+            }
+        }
+        extents.truncate(r);
+        extents.extend(moved);
+        extents.retain(|e| e.len > 0);
+        extents
+    }
+
+    // Each extent is moved at most once, highest file id first, and only to the left. The space a
+    // file leaves behind goes back into the free list. With the puzzle's layout no file still to
+    // be moved can reach it, but it matters when ids are not in disk order, eg when compacting a
+    // disk a second time.
+    fn compact_files(&self, best_fit: bool, log: &mut Vec<Move>) -> Vec<Extent> {
+        let mut extents = self.extents();
+        let max_len = extents.iter().map(|e| e.len).max().unwrap_or(0);
+        let mut free = FreeSpans::new(self.free_spans(), max_len, best_fit);
+        let mut order = (0..extents.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| Reverse((extents[i].file_id, extents[i].start)));
+        for i in order {
+            let e = &mut extents[i];
+            let Some((span_start, _)) = free.find(e.len, e.start) else {
+                continue;
+            };
+            free.take(span_start, e.len);
+            free.release(e.start, e.len);
+            log.push(Move {
+                file_id: e.file_id,
+                from: e.start,
+                to: span_start,
+                len: e.len,
+            });
+            e.start = span_start;
+        }
+        extents
+    }
+
+    // Files only ever move left here, so moving them in disk order never overwrites one that has
+    // not moved yet.
+    fn defragment(&self, log: &mut Vec<Move>) -> Vec<Extent> {
+        let mut extents = self.extents();
+        let mut pos = 0;
+        for e in extents.iter_mut() {
+            if e.start != pos {
+                log.push(Move {
+                    file_id: e.file_id,
+                    from: e.start,
+                    to: pos,
+                    len: e.len,
+                });
+            }
+            e.start = pos;
+            pos += e.len;
+        }
+        extents
+    }
+}
+
+// The puzzle's notation, one character per block with '.' for free space. When some file id has
+// more than one digit, every block is instead padded to the widest id and separated by spaces.
+impl fmt::Display for DiskMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .blocks
+            .iter()
+            .filter_map(|b| b.file_id)
+            .map(|id| id.to_string().len())
+            .max()
+            .unwrap_or(1);
+        for (i, cell) in self.cells().into_iter().enumerate() {
+            if width > 1 && i > 0 {
+                write!(f, " ")?;
+            }
+            match cell {
+                Some(id) => write!(f, "{:>width$}", id)?,
+                None => write!(f, "{:>width$}", ".")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_disk_map(test_input: Option<Vec<String>>) -> DiskMap {
+    let bytes = test_input
+        .map(|lines| lines.concat().into_bytes())
+        .unwrap_or_else(|| aoc_read_day_bytes(9));
+    DiskMap::parse(&bytes)
+}
+
+pub fn day9(test_input: Option<Vec<String>>, strategy: Strategy) -> usize {
+    read_disk_map(test_input).compact(strategy).checksum()
+}
+
+pub fn day9_part1(test_input: Option<Vec<String>>) -> usize {
+    day9(test_input, Strategy::BlockLevel)
+}
+
+pub fn day9_part2(test_input: Option<Vec<String>>) -> usize {
+    day9(test_input, Strategy::FirstFit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_rng;
+
+    const EXAMPLE: &str = "2333133121414131402";
+
+    fn example() -> Vec<String> {
+        vec![EXAMPLE.to_string()]
+    }
+
+    #[test]
+    fn test_day9_part1() {
+        assert_eq!(day9_part1(Some(example())), 1928);
+    }
+
+    #[test]
+    fn test_day9_part2() {
+        assert_eq!(day9_part2(Some(example())), 2858);
+    }
+
+    #[test]
+    fn test_strategies() {
+        let disk = read_disk_map(Some(example()));
+        for strategy in [
+            Strategy::BlockLevel,
+            Strategy::FirstFit,
+            Strategy::BestFit,
+            Strategy::DefragmentToEnd,
+        ] {
+            let compacted = disk.compact(strategy);
+            assert_eq!(compacted.size(), disk.size(), "{:?}", strategy);
+            let mut before = disk.cells().into_iter().flatten().collect::<Vec<_>>();
+            let mut after = compacted.cells().into_iter().flatten().collect::<Vec<_>>();
+            before.sort_unstable();
+            after.sort_unstable();
+            assert_eq!(before, after, "{:?} lost or duplicated blocks", strategy);
+        }
+
+        // 0....1.2: first fit puts file 2 in the big gap and file 1 next to it, best fit puts
+        // file 2 in the small gap and leaves the big one for file 1
+        let disk = DiskMap::parse(b"14111");
+        let layout = |strategy| {
+            disk.compact(strategy)
+                .cells()
+                .iter()
+                .map(|c| c.map_or('.', |id| char::from(b'0' + id as u8)))
+                .collect::<String>()
+        };
+        assert_eq!(layout(Strategy::FirstFit), "021.....");
+        assert_eq!(layout(Strategy::BestFit), "01....2.");
+        assert_eq!(layout(Strategy::DefragmentToEnd), "012.....");
+
+        let defragmented = read_disk_map(Some(example())).compact(Strategy::DefragmentToEnd);
+        assert_eq!(defragmented.free_spans(), vec![(28, 14)]);
+        assert_eq!(defragmented.blocks.len(), 11);
+    }
+
+    #[test]
+    fn test_checksum_is_layout_only() {
+        let disk = DiskMap::parse(b"12345");
+        // 0..111....22222
+        assert_eq!(
+            disk.checksum(),
+            (3..6).sum::<usize>() + 2 * (10..15).sum::<usize>()
+        );
+        let compacted = disk.compact(Strategy::BlockLevel);
+        // 022111222......
+        assert_eq!(
+            compacted.cells()[..9],
+            [
+                Some(0),
+                Some(2),
+                Some(2),
+                Some(1),
+                Some(1),
+                Some(1),
+                Some(2),
+                Some(2),
+                Some(2)
+            ]
+        );
+        assert_eq!(compacted.checksum(), 60);
+    }
+
+    #[test]
+    fn test_render() {
+        let disk = read_disk_map(Some(example()));
+        assert_eq!(
+            disk.to_string(),
+            "00...111...2...333.44.5555.6666.777.888899"
+        );
+        assert_eq!(
+            disk.compact(Strategy::BlockLevel).to_string(),
+            "0099811188827773336446555566.............."
+        );
+        assert_eq!(
+            disk.compact(Strategy::FirstFit).to_string(),
+            "00992111777.44.333....5555.6666.....8888.."
+        );
+        // file 10 needs two characters
+        let disk = DiskMap::parse(b"101010101010101010102");
+        assert_eq!(disk.to_string(), " 0  1  2  3  4  5  6  7  8  9 10 10");
+        assert_eq!(DiskMap::parse(b"").to_string(), "");
+    }
+
+    #[test]
+    fn test_move_log() {
+        let disk = read_disk_map(Some(example()));
+        for strategy in [
+            Strategy::BlockLevel,
+            Strategy::FirstFit,
+            Strategy::BestFit,
+            Strategy::DefragmentToEnd,
+        ] {
+            let (compacted, log) = disk.compact_with_log(strategy);
+            let mut replayed = disk.clone();
+            for m in &log {
+                replayed.apply_move(m);
+            }
+            assert_eq!(replayed, compacted, "{:?}", strategy);
+        }
+        // only the runs around the source and target change, merged with their neighbours
+        let mut small = DiskMap::parse(b"12345");
+        small.apply_move(&Move {
+            file_id: 1,
+            from: 3,
+            to: 1,
+            len: 3,
+        });
+        assert_eq!(small, DiskMap::parse(b"10365"));
+        assert_eq!(small.to_string(), "0111......22222");
+        // a file that is not there, space that is not free, or a range past the end
+        assert!(!small.fill(0, 2, Some(0), None));
+        assert!(!small.fill(3, 2, None, Some(7)));
+        assert!(!small.fill(14, 3, Some(2), None));
+        assert_eq!(small.to_string(), "0111......22222");
+        let (_, log) = disk.compact_with_log(Strategy::FirstFit);
+        assert_eq!(
+            log,
+            vec![
+                Move {
+                    file_id: 9,
+                    from: 40,
+                    to: 2,
+                    len: 2
+                },
+                Move {
+                    file_id: 7,
+                    from: 32,
+                    to: 8,
+                    len: 3
+                },
+                Move {
+                    file_id: 4,
+                    from: 19,
+                    to: 12,
+                    len: 2
+                },
+                Move {
+                    file_id: 2,
+                    from: 11,
+                    to: 4,
+                    len: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let disk = read_disk_map(Some(example()));
+        let block_level = disk.compact(Strategy::BlockLevel);
+        let first_fit = disk.compact(Strategy::FirstFit);
+        let changes = block_level.diff(&first_fit);
+        // both start with 0099, then differ at position 4: 8 vs 2
+        assert_eq!(
+            changes[0],
+            LayoutChange {
+                start: 4,
+                len: 1,
+                before: Some(8),
+                after: Some(2)
+            }
+        );
+        assert!(block_level.diff(&block_level).is_empty());
+        // every differing position is covered exactly once
+        let differing = block_level
+            .cells()
+            .iter()
+            .zip(first_fit.cells().iter())
+            .filter(|(a, b)| a != b)
+            .count();
+        assert_eq!(changes.iter().map(|c| c.len).sum::<usize>(), differing);
+    }
+
+    // compact_files on cells: every extent once, highest id first, into the leftmost (or smallest)
+    // run of free cells left of it
+    fn compact_files_by_cells(disk: &DiskMap, best_fit: bool) -> Vec<Option<usize>> {
+        let mut cells = disk.cells();
+        let mut extents = disk.extents();
+        extents.sort_by_key(|e| Reverse((e.file_id, e.start)));
+        for e in extents {
+            let mut runs = Vec::new();
+            let mut pos = 0;
+            while pos < e.start {
+                let len = cells[pos..e.start]
+                    .iter()
+                    .take_while(|c| c.is_none())
+                    .count();
+                if len >= e.len {
+                    runs.push((len, pos));
                 }
+                pos += len.max(1);
             }
-            cur_fileid_to_move -= 1;
-            cur_fileidx_to_move -= 2;
-        } else {
-            if cur_fileidx_to_move == 0 {
-                cur_fileid_to_move -= 1;
-                cur_fileidx_to_move = compacted_disk.len() - 1;
+            let chosen = if best_fit {
+                runs.into_iter().min()
             } else {
-                cur_fileidx_to_move -= 1;
+                runs.into_iter().min_by_key(|&(_, start)| start)
+            };
+            if let Some((_, to)) = chosen {
+                cells[e.start..e.start + e.len].fill(None);
+                cells[to..to + e.len].fill(Some(e.file_id));
             }
         }
+        cells
     }
 
-    let mut sum = 0;
-    let mut actual_pos = 0;
-    for block in compacted_disk.iter() {
-        for _ in 0..block.run_length {
-            sum += actual_pos * block.file_id.unwrap_or(0);
-            actual_pos += 1;
+    #[test]
+    fn test_compact_reuses_freed_space() {
+        let disk = read_disk_map(Some(example()));
+        for best_fit in [false, true] {
+            let strategy = if best_fit {
+                Strategy::BestFit
+            } else {
+                Strategy::FirstFit
+            };
+            let once = disk.compact(strategy);
+            let twice = once.compact(strategy);
+            assert_eq!(once.cells(), compact_files_by_cells(&disk, best_fit));
+            assert_eq!(twice.cells(), compact_files_by_cells(&once, best_fit));
+        }
+        // file 1 moves first and frees the gap that file 0 then moves into
+        let disk = DiskMap::from_blocks([(None, 2), (Some(0), 2), (None, 1), (Some(1), 2)].map(
+            |(file_id, run_length)| Block {
+                file_id,
+                run_length,
+            },
+        ));
+        assert_eq!(disk.to_string(), "..00.11");
+        assert_eq!(disk.compact(Strategy::FirstFit).to_string(), "1100...");
+
+        // files in a random order on the disk
+        let mut rng = test_rng(9);
+        let mut next = |n: u64| rng(n) as usize;
+        for _ in 0..300 {
+            let count = 1 + next(12);
+            let mut ids = (0..count).collect::<Vec<_>>();
+            for i in (1..count).rev() {
+                ids.swap(i, next(i as u64 + 1));
+            }
+            let blocks = ids.into_iter().flat_map(|id| {
+                [
+                    Block {
+                        file_id: None,
+                        run_length: next(4),
+                    },
+                    Block {
+                        file_id: Some(id),
+                        run_length: 1 + next(4),
+                    },
+                ]
+            });
+            let disk = DiskMap::from_blocks(blocks.collect::<Vec<_>>());
+            for (strategy, best_fit) in [(Strategy::FirstFit, false), (Strategy::BestFit, true)] {
+                let compacted = disk.compact(strategy);
+                assert_eq!(
+                    compacted.cells(),
+                    compact_files_by_cells(&disk, best_fit),
+                    "{:?} of {}",
+                    strategy,
+                    disk
+                );
+                assert_eq!(
+                    compacted.compact(strategy).cells(),
+                    compact_files_by_cells(&compacted, best_fit),
+                    "{:?} twice of {}",
+                    strategy,
+                    disk
+                );
+            }
         }
     }
-    sum
 }
@@ -1,75 +1,265 @@
-use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
-
 use crate::util::*;
-use std::collections::VecDeque;
 
 const CARDINAL_DIRECTIONS: [(isize, isize); 4] = [(0, 1), (0, -1), (1, 0), (-1, 0)];
 
-fn bfs(grid: &Vec<u8>, start: isize, width: usize, height: usize, is_part_2: bool) -> usize {
-    let mut visited = vec![false; grid.len()];
-    let mut queue = VecDeque::new();
-    queue.push_back((start, 0));
-    let mut result = 0;
+const SUMMIT: u8 = 9;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trailhead {
+    pub x: usize,
+    pub y: usize,
+    // number of summits reachable from here
+    pub score: usize,
+    // number of distinct trails from here to any summit
+    pub rating: usize,
+    // the reachable summits as (x, y) in reading order
+    pub summits: Vec<(usize, usize)>,
+}
+
+pub struct TopoMap {
+    pub width: usize,
+    pub height: usize,
+    // None for impassable cells, written as '.'
+    heights: Vec<Option<u8>>,
+}
 
-    while let Some((current, current_value)) = queue.pop_front() {
-        if visited[current as usize] {
-            continue;
+impl TopoMap {
+    pub fn from_lines(lines: &[String]) -> Self {
+        let lines = lines
+            .iter()
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>();
+        let height = lines.len();
+        let width = lines.first().map_or(0, |line| line.len());
+        assert!(
+            lines.iter().all(|line| line.len() == width),
+            "map rows must all have the same width"
+        );
+        let heights = lines
+            .iter()
+            .flat_map(|line| line.bytes())
+            .map(|byte| match byte {
+                b'0'..=b'9' => Some(byte - b'0'),
+                b'.' => None,
+                _ => panic!("unexpected map cell {:?}", byte as char),
+            })
+            .collect();
+        TopoMap {
+            width,
+            height,
+            heights,
         }
-        visited[current as usize] = true;
-        result += (current_value == 9) as usize;
-
-        let current_x = current % (width as isize);
-        let current_y = current / (width as isize);
-
-        for (dx, dy) in CARDINAL_DIRECTIONS {
-            let nx = current_x + dx;
-            let ny = current_y + dy;
-            let n_idx = ny * (width as isize) + nx;
-            let need_to_visit = ny >= 0
-                && ny < height as isize
-                && nx >= 0
-                && nx < width as isize
-                && !visited[n_idx as usize]
-                && grid[n_idx as usize] == current_value + 1;
-            if need_to_visit {
-                queue.push_back((n_idx, current_value + 1));
+    }
+
+    // None for impassable cells and for (x, y) outside the map.
+    pub fn height_at(&self, x: usize, y: usize) -> Option<u8> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.heights[y * self.width + x]
+    }
+
+    fn position(&self, idx: usize) -> (usize, usize) {
+        (idx % self.width, idx / self.width)
+    }
+
+    // Indices of the cells exactly one higher than `idx`.
+    fn uphill(&self, idx: usize) -> impl Iterator<Item = usize> + '_ {
+        let (x, y) = self.position(idx);
+        let next = self.heights[idx].map(|h| h + 1);
+        CARDINAL_DIRECTIONS.iter().filter_map(move |&(dx, dy)| {
+            let nx = x.checked_add_signed(dx).filter(|&nx| nx < self.width)?;
+            let ny = y.checked_add_signed(dy).filter(|&ny| ny < self.height)?;
+            let n_idx = ny * self.width + nx;
+            (next.is_some() && self.heights[n_idx] == next).then_some(n_idx)
+        })
+    }
+
+    // Score, rating and summits for every trailhead, in reading order. Ratings come from one pass
+    // over the heights from the summits down: a cell's rating is the sum of its uphill neighbours'.
+    // Summits come from a search per trailhead, sharing one visited array stamped with the
+    // trailhead's number, so memory stays O(cells).
+    pub fn trailheads(&self) -> Vec<Trailhead> {
+        let mut ratings = vec![0usize; self.heights.len()];
+        let mut by_height: [Vec<usize>; SUMMIT as usize + 1] = Default::default();
+        for (idx, h) in self.heights.iter().enumerate() {
+            if let Some(h) = *h {
+                by_height[h as usize].push(idx);
+            }
+        }
+        for &idx in &by_height[SUMMIT as usize] {
+            ratings[idx] = 1;
+        }
+        for level in by_height[..SUMMIT as usize].iter().rev() {
+            for &idx in level {
+                ratings[idx] = self.uphill(idx).map(|n_idx| ratings[n_idx]).sum();
             }
         }
 
-        if is_part_2 {
-            visited[current as usize] = false;
+        let mut visited = vec![0usize; self.heights.len()];
+        let mut stack = Vec::new();
+        by_height[0]
+            .iter()
+            .enumerate()
+            .map(|(n, &idx)| {
+                let stamp = n + 1;
+                let mut summits = Vec::new();
+                visited[idx] = stamp;
+                stack.push(idx);
+                while let Some(cur) = stack.pop() {
+                    if self.heights[cur] == Some(SUMMIT) {
+                        summits.push(cur);
+                        continue;
+                    }
+                    for n_idx in self.uphill(cur) {
+                        if visited[n_idx] != stamp {
+                            visited[n_idx] = stamp;
+                            stack.push(n_idx);
+                        }
+                    }
+                }
+                summits.sort_unstable();
+                let (x, y) = self.position(idx);
+                Trailhead {
+                    x,
+                    y,
+                    score: summits.len(),
+                    rating: ratings[idx],
+                    summits: summits.into_iter().map(|s| self.position(s)).collect(),
+                }
+            })
+            .collect()
+    }
+
+    // Every distinct trail starting at (x, y), each as the (x, y) of its cells from height 0 to 9.
+    // Empty unless (x, y) is a trailhead, including when it is outside the map.
+    pub fn trails_from(&self, x: usize, y: usize) -> Vec<Vec<(usize, usize)>> {
+        let mut trails = Vec::new();
+        if self.height_at(x, y) != Some(0) {
+            return trails;
+        }
+        let start = y * self.width + x;
+        let mut stack = vec![vec![start]];
+        while let Some(trail) = stack.pop() {
+            let last = *trail.last().unwrap();
+            if self.heights[last] == Some(SUMMIT) {
+                trails.push(trail.iter().map(|&idx| self.position(idx)).collect());
+                continue;
+            }
+            for n_idx in self.uphill(last) {
+                let mut longer = trail.clone();
+                longer.push(n_idx);
+                stack.push(longer);
+            }
         }
+        trails.sort_unstable();
+        trails
     }
-    result
 }
 
-pub fn day10(is_part_2: bool) -> usize {
-    let mut height = 1;
-    let mut width = 0;
-    let input = aoc_read_day_bytes(10)
+pub fn day10(test_input: Option<Vec<String>>, is_part_2: bool) -> usize {
+    let lines = test_input.unwrap_or_else(|| aoc_read_day_lines(10));
+    TopoMap::from_lines(&lines)
+        .trailheads()
         .iter()
-        .filter_map(|byte| {
-            width += (height == 1 && *byte != b'\n') as usize;
-            height += (*byte == b'\n') as usize;
-            if *byte == b'\n' {
-                return None;
-            }
-            Some(byte - b'0')
-        })
-        .collect::<Vec<u8>>();
-
-    input
-        .par_iter()
-        .enumerate()
-        .filter(|(_, &byte)| byte == 0)
-        .map(|(start, _)| bfs(&input, start as isize, width, height, is_part_2))
+        .map(|t| if is_part_2 { t.rating } else { t.score })
         .sum()
 }
 
-pub fn day10_part1() -> usize {
-    day10(false)
+pub fn day10_part1(test_input: Option<Vec<String>>) -> usize {
+    day10(test_input, false)
 }
 
-pub fn day10_part2() -> usize {
-    day10(true)
+pub fn day10_part2(test_input: Option<Vec<String>>) -> usize {
+    day10(test_input, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "89010123
+78121874
+87430965
+96549874
+45678903
+32019012
+01329801
+10456732";
+
+    fn lines(s: &str) -> Vec<String> {
+        s.lines().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_day10_part1() {
+        assert_eq!(day10_part1(Some(lines(EXAMPLE))), 36);
+    }
+
+    #[test]
+    fn test_day10_part2() {
+        assert_eq!(day10_part2(Some(lines(EXAMPLE))), 81);
+    }
+
+    #[test]
+    fn test_trailheads() {
+        let map = TopoMap::from_lines(&lines(EXAMPLE));
+        let trailheads = map.trailheads();
+        assert_eq!(
+            trailheads.iter().map(|t| t.score).collect::<Vec<_>>(),
+            vec![5, 6, 5, 3, 1, 3, 5, 3, 5]
+        );
+        assert_eq!(
+            trailheads.iter().map(|t| t.rating).collect::<Vec<_>>(),
+            vec![20, 24, 10, 4, 1, 4, 5, 8, 5]
+        );
+        for t in &trailheads {
+            assert_eq!(t.summits.len(), t.score);
+            assert!(t
+                .summits
+                .iter()
+                .all(|&(x, y)| map.height_at(x, y) == Some(9)));
+            assert_eq!(map.trails_from(t.x, t.y).len(), t.rating);
+        }
+    }
+
+    #[test]
+    fn test_impassable_cells() {
+        let map = TopoMap::from_lines(&lines(
+            "..90..9
+...1.98
+...2..7
+6543456
+765.987
+876....
+987....",
+        ));
+        let trailheads = map.trailheads();
+        assert_eq!(trailheads.len(), 1);
+        assert_eq!(trailheads[0].score, 4);
+        assert_eq!(trailheads[0].summits, vec![(6, 0), (5, 1), (4, 4), (0, 6)]);
+
+        let map = TopoMap::from_lines(&lines(
+            ".....0.
+..4321.
+..5..2.
+..6543.
+..7..4.
+..8765.
+..9....",
+        ));
+        assert_eq!(map.trailheads()[0].rating, 3);
+        let trails = map.trails_from(5, 0);
+        assert_eq!(trails.len(), 3);
+        for trail in &trails {
+            assert_eq!(trail.first(), Some(&(5, 0)));
+            assert_eq!(trail.last(), Some(&(2, 6)));
+            assert_eq!(trail.len(), 10);
+        }
+        assert!(map.trails_from(0, 0).is_empty());
+        assert!(map.trails_from(7, 0).is_empty());
+        assert!(map.trails_from(0, 7).is_empty());
+        assert!(map.trails_from(usize::MAX, usize::MAX).is_empty());
+        assert_eq!(map.height_at(7, 0), None);
+    }
 }
@@ -1,77 +1,382 @@
-use std::collections::HashMap;
-
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use smallvec::{smallvec, SmallVec};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
 use crate::util::aoc_read_day_lines;
 
-pub fn day11_part1() -> usize {
-    let line = aoc_read_day_lines(11).pop().unwrap();
-    let stones = line
-        .split_whitespace()
-        .map(|s| s.parse::<usize>().unwrap())
-        .collect::<Vec<usize>>();
+// Stone counts can outgrow u64 after a few hundred blinks, so the counter is generic over them.
+pub trait Count: Clone + fmt::Display {
+    fn zero() -> Self;
+    fn one() -> Self;
+    fn add(&mut self, other: &Self);
+}
+
+impl Count for u64 {
+    fn zero() -> Self {
+        0
+    }
+    fn one() -> Self {
+        1
+    }
+    fn add(&mut self, other: &Self) {
+        *self = self
+            .checked_add(*other)
+            .expect("stone count overflows u64, use u128 or BigCount");
+    }
+}
 
-    stones
-        .par_iter()
-        .map(|stone| blinker(*stone, 25, &mut HashMap::new()))
-        .sum()
+impl Count for u128 {
+    fn zero() -> Self {
+        0
+    }
+    fn one() -> Self {
+        1
+    }
+    fn add(&mut self, other: &Self) {
+        *self = self
+            .checked_add(*other)
+            .expect("stone count overflows u128, use BigCount");
+    }
 }
 
-pub fn day11_part2() -> usize {
-    let line = aoc_read_day_lines(11).pop().unwrap();
-    let stones = line
-        .split_whitespace()
-        .map(|s| s.parse::<usize>().unwrap())
-        .collect::<Vec<usize>>();
+const BIG_COUNT_LIMB: u32 = 1_000_000_000;
 
-    stones
-        .par_iter()
-        .map(|stone| blinker(*stone, 75, &mut HashMap::new()))
-        .sum()
+// Unbounded count supporting only addition, which is all the counter needs. Little endian limbs in
+// base 10^9 so that printing it in decimal is trivial.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BigCount {
+    limbs: Vec<u32>,
 }
 
-fn blinker(stone: usize, blinks: usize, cache: &mut HashMap<(usize, usize), usize>) -> usize {
-    if blinks == 0 {
-        return 1;
+impl Count for BigCount {
+    fn zero() -> Self {
+        BigCount { limbs: vec![] }
+    }
+    fn one() -> Self {
+        BigCount { limbs: vec![1] }
+    }
+    fn add(&mut self, other: &Self) {
+        if self.limbs.len() < other.limbs.len() {
+            self.limbs.resize(other.limbs.len(), 0);
+        }
+        let mut carry = 0;
+        for (i, limb) in self.limbs.iter_mut().enumerate() {
+            let sum = *limb + other.limbs.get(i).copied().unwrap_or(0) + carry;
+            carry = (sum >= BIG_COUNT_LIMB) as u32;
+            *limb = sum % BIG_COUNT_LIMB;
+            if carry == 0 && i >= other.limbs.len() {
+                break;
+            }
+        }
+        if carry > 0 {
+            self.limbs.push(carry);
+        }
     }
-    if let Some(ret) = cache.get(&(stone, blinks)) {
-        return *ret;
+}
+
+impl fmt::Display for BigCount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Some((most_significant, rest)) = self.limbs.split_last() else {
+            return write!(f, "0");
+        };
+        write!(f, "{}", most_significant)?;
+        for limb in rest.iter().rev() {
+            write!(f, "{:09}", limb)?;
+        }
+        Ok(())
     }
-    if stone == 0 {
-        let ret = blinker(1, blinks - 1, cache);
-        cache.insert((stone, blinks), ret);
-        return ret;
+}
+
+fn num_digits(mut n: u64, base: u64) -> usize {
+    let mut count = 1;
+    while n >= base {
+        count += 1;
+        n /= base;
     }
-    let num_digits = {
-        let mut n = stone;
-        let mut count = 0;
-        while n > 0 {
-            count += 1;
-            n /= 10;
+    count
+}
+
+// Splits the digits of `num` into `parts` equally long groups, most significant first.
+fn split_digits(num: u64, num_digits: usize, parts: usize, base: u64) -> SmallVec<[u64; 4]> {
+    assert!(num_digits.is_multiple_of(parts));
+    let divisor = base.pow((num_digits / parts) as u32);
+    let mut rest = num;
+    let mut groups: SmallVec<[u64; 4]> = (0..parts)
+        .map(|_| {
+            let group = rest % divisor;
+            rest /= divisor;
+            group
+        })
+        .collect();
+    groups.reverse();
+    groups
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleError {
+    // digits need a base of at least 2
+    BaseTooSmall(u64),
+    // a split has to produce at least 2 stones
+    TooFewParts(usize),
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleError::BaseTooSmall(base) => write!(f, "base must be at least 2, got {}", base),
+            RuleError::TooFewParts(parts) => {
+                write!(f, "stones must split into at least 2 parts, got {}", parts)
+            }
         }
-        count
+    }
+}
+
+// What a stone turns into on a blink: 0 becomes 1, a stone whose digit count (in `base`) is a
+// multiple of `split_parts` splits into that many stones, and any other stone is multiplied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuleSet {
+    multiplier: u64,
+    split_parts: usize,
+    base: u64,
+}
+
+impl RuleSet {
+    pub const PUZZLE: RuleSet = RuleSet {
+        multiplier: 2024,
+        split_parts: 2,
+        base: 10,
     };
-    if num_digits % 2 == 0 {
-        let (first_half, second_half) = split_int_in_two(stone, num_digits);
-        let ret1 = blinker(first_half, blinks - 1, cache);
-        let ret2 = blinker(second_half, blinks - 1, cache);
-        let ret = ret1 + ret2;
-        cache.insert((stone, blinks), ret);
-        return ret;
+
+    pub fn new(multiplier: u64, split_parts: usize, base: u64) -> Result<RuleSet, RuleError> {
+        if base < 2 {
+            return Err(RuleError::BaseTooSmall(base));
+        }
+        if split_parts < 2 {
+            return Err(RuleError::TooFewParts(split_parts));
+        }
+        Ok(RuleSet {
+            multiplier,
+            split_parts,
+            base,
+        })
+    }
+
+    // None if the stone overflows a u64.
+    pub fn apply(&self, stone: u64) -> Option<SmallVec<[u64; 4]>> {
+        if stone == 0 {
+            return Some(smallvec![1]);
+        }
+        let num_digits = num_digits(stone, self.base);
+        if num_digits.is_multiple_of(self.split_parts) {
+            return Some(split_digits(stone, num_digits, self.split_parts, self.base));
+        }
+        Some(smallvec![stone.checked_mul(self.multiplier)?])
+    }
+
+    // Every value reachable from the given stones, the stones themselves included, sorted. Blinking
+    // never leaves this set. None if there are more than `limit` of them or a value overflows.
+    pub fn closure(&self, stones: &[u64], limit: usize) -> Option<Vec<u64>> {
+        let mut seen = stones.iter().copied().collect::<HashSet<_>>();
+        let mut queue = seen.iter().copied().collect::<Vec<_>>();
+        while let Some(stone) = queue.pop() {
+            for next in self.apply(stone)? {
+                if seen.insert(next) {
+                    if seen.len() > limit {
+                        return None;
+                    }
+                    queue.push(next);
+                }
+            }
+        }
+        let mut values = seen.into_iter().collect::<Vec<_>>();
+        values.sort_unstable();
+        Some(values)
+    }
+
+    pub fn transition_matrix(&self, stones: &[u64], limit: usize) -> Option<TransitionMatrix> {
+        let values = self.closure(stones, limit)?;
+        let index = values
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| (v, i))
+            .collect::<HashMap<_, _>>();
+        let mut entries = vec![vec![0; values.len()]; values.len()];
+        for (i, &v) in values.iter().enumerate() {
+            for next in self.apply(v)? {
+                entries[i][index[&next]] += 1;
+            }
+        }
+        Some(TransitionMatrix {
+            values,
+            index,
+            entries,
+        })
+    }
+}
+
+// One blink as a linear map over the closed set of values: `entries[i][j]` is how many stones of
+// value `values[j]` a single stone of value `values[i]` becomes.
+pub struct TransitionMatrix {
+    pub values: Vec<u64>,
+    index: HashMap<u64, usize>,
+    pub entries: Vec<Vec<u64>>,
+}
+
+impl TransitionMatrix {
+    fn identity(n: usize) -> Vec<Vec<u64>> {
+        (0..n)
+            .map(|i| (0..n).map(|j| (i == j) as u64).collect())
+            .collect()
+    }
+
+    fn mul_mod(a: &[Vec<u64>], b: &[Vec<u64>], modulus: u64) -> Vec<Vec<u64>> {
+        let n = a.len();
+        let mut out = vec![vec![0; n]; n];
+        for i in 0..n {
+            for k in 0..n {
+                if a[i][k] == 0 {
+                    continue;
+                }
+                for j in 0..n {
+                    out[i][j] = ((out[i][j] as u128 + a[i][k] as u128 * b[k][j] as u128)
+                        % modulus as u128) as u64;
+                }
+            }
+        }
+        out
+    }
+
+    // Number of stones after `blinks` blinks, modulo `modulus`, by raising the matrix to the
+    // power of `blinks` with repeated squaring. O(k^3 log blinks) for k values, so it only pays
+    // off over StoneCounter when the blink count is far beyond what counting can reach. None for
+    // a modulus of 0 or a stone outside the closed set.
+    pub fn count_mod(&self, stones: &[u64], blinks: u64, modulus: u64) -> Option<u64> {
+        if modulus == 0 {
+            return None;
+        }
+        let n = self.values.len();
+        let mut result = Self::identity(n);
+        let mut power = self
+            .entries
+            .iter()
+            .map(|row| row.iter().map(|&e| e % modulus).collect())
+            .collect::<Vec<Vec<u64>>>();
+        let mut remaining = blinks;
+        while remaining > 0 {
+            if remaining & 1 == 1 {
+                result = Self::mul_mod(&result, &power, modulus);
+            }
+            power = Self::mul_mod(&power, &power, modulus);
+            remaining >>= 1;
+        }
+        let mut total = 0;
+        for stone in stones {
+            let i = *self.index.get(stone)?;
+            for &count in &result[i] {
+                total = (total + count) % modulus;
+            }
+        }
+        Some(total)
+    }
+}
+
+// The stones as a multiset: the order of the stones never matters, so every stone with the same
+// value is evolved once per blink no matter how many there are.
+pub struct StoneCounter<C: Count = u64> {
+    stones: HashMap<u64, C>,
+    generation: usize,
+    rules: RuleSet,
+}
+
+impl<C: Count> StoneCounter<C> {
+    pub fn new(stones: &[u64]) -> Self {
+        Self::with_rules(stones, RuleSet::PUZZLE)
+    }
+
+    pub fn with_rules(stones: &[u64], rules: RuleSet) -> Self {
+        let mut counts: HashMap<u64, C> = HashMap::new();
+        for &stone in stones {
+            counts.entry(stone).or_insert_with(C::zero).add(&C::one());
+        }
+        StoneCounter {
+            stones: counts,
+            generation: 0,
+            rules,
+        }
+    }
+
+    // Whitespace separated stone values.
+    pub fn from_line(line: &str) -> Self {
+        let stones = line
+            .split_whitespace()
+            .map(|s| s.parse().expect("stone"))
+            .collect::<Vec<u64>>();
+        Self::new(&stones)
+    }
+
+    pub fn blink(&mut self) {
+        let mut next: HashMap<u64, C> = HashMap::with_capacity(self.stones.len() * 2);
+        for (&stone, count) in self.stones.iter() {
+            let stones = self
+                .rules
+                .apply(stone)
+                .unwrap_or_else(|| panic!("stone {} overflows when multiplied", stone));
+            for next_stone in stones {
+                next.entry(next_stone).or_insert_with(C::zero).add(count);
+            }
+        }
+        self.stones = next;
+        self.generation += 1;
+    }
+
+    // Blinks `blinks` more times, returning the number of distinct values after each blink.
+    pub fn blink_n(&mut self, blinks: usize) -> Vec<usize> {
+        (0..blinks)
+            .map(|_| {
+                self.blink();
+                self.distinct()
+            })
+            .collect()
+    }
+
+    // Number of blinks so far.
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    // Number of distinct stone values.
+    pub fn distinct(&self) -> usize {
+        self.stones.len()
+    }
+
+    // How many stones have the given value.
+    pub fn count_of(&self, stone: u64) -> C {
+        self.stones.get(&stone).cloned().unwrap_or_else(C::zero)
     }
 
-    let ret = blinker(stone * 2024, blinks - 1, cache);
-    cache.insert((stone, blinks), ret);
-    return ret;
+    // Total number of stones.
+    pub fn total(&self) -> C {
+        let mut total = C::zero();
+        for count in self.stones.values() {
+            total.add(count);
+        }
+        total
+    }
+}
+
+pub fn day11(test_input: Option<Vec<String>>, blinks: usize) -> u64 {
+    let lines = test_input.unwrap_or_else(|| aoc_read_day_lines(11));
+    let mut counter = StoneCounter::<u64>::from_line(&lines.join(" "));
+    counter.blink_n(blinks);
+    counter.total()
 }
 
-fn split_int_in_two(num: usize, num_digits: usize) -> (usize, usize) {
-    assert!(num_digits % 2 == 0);
-    let half_digits = num_digits / 2;
-    let divisor = 10_usize.pow(half_digits as u32);
-    let second_half = num % divisor;
-    let first_half = num / divisor;
-    (first_half, second_half)
+pub fn day11_part1(test_input: Option<Vec<String>>) -> u64 {
+    day11(test_input, 25)
+}
+
+pub fn day11_part2(test_input: Option<Vec<String>>) -> u64 {
+    day11(test_input, 75)
 }
 
 #[cfg(test)]
@@ -79,20 +384,159 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_split_int_in_two() {
-        assert_eq!(split_int_in_two(10, 2), (1, 0));
-        assert_eq!(split_int_in_two(99, 2), (9, 9));
-        assert_eq!(split_int_in_two(1000, 4), (10, 0));
-        assert_eq!(split_int_in_two(123456, 6), (123, 456));
-        assert_eq!(split_int_in_two(1234567890, 10), (12345, 67890));
-        assert_eq!(split_int_in_two(1234567890123456, 16), (12345678, 90123456));
+    fn test_split_digits() {
+        let halves = |num: u64, num_digits: usize| split_digits(num, num_digits, 2, 10).to_vec();
+        assert_eq!(halves(10, 2), vec![1, 0]);
+        assert_eq!(halves(99, 2), vec![9, 9]);
+        assert_eq!(halves(1000, 4), vec![10, 0]);
+        assert_eq!(halves(123456, 6), vec![123, 456]);
+        assert_eq!(halves(1234567890, 10), vec![12345, 67890]);
+        assert_eq!(halves(1234567890123456, 16), vec![12345678, 90123456]);
+        assert_eq!(halves(123456789012345678, 18), vec![123456789, 12345678]);
         assert_eq!(
-            split_int_in_two(123456789012345678, 18),
-            (123456789, 12345678)
+            halves(12345678901234567890, 20),
+            vec![1234567890, 1234567890]
         );
+        assert_eq!(num_digits(12345678901234567890, 10), 20);
         assert_eq!(
-            split_int_in_two(12345678901234567890, 20),
-            (1234567890, 1234567890)
+            RuleSet::PUZZLE
+                .apply(12345678901234567890)
+                .unwrap()
+                .as_slice(),
+            &[1234567890, 1234567890]
+        );
+    }
+
+    #[test]
+    fn test_day11_part1() {
+        assert_eq!(day11_part1(Some(vec!["125 17".to_string()])), 55312);
+    }
+
+    #[test]
+    fn test_stone_counter() {
+        let mut counter = StoneCounter::<u64>::from_line("125 17");
+        // 253000 1 7, then 253 0 2024 14168, then 512072 1 20 24 28676032
+        counter.blink();
+        assert_eq!((counter.total(), counter.distinct()), (3, 3));
+        counter.blink_n(2);
+        assert_eq!(counter.generation(), 3);
+        assert_eq!((counter.total(), counter.distinct()), (5, 5));
+        // 512 72 2024 2 0 2 4 2867 6032
+        counter.blink();
+        assert_eq!(counter.total(), 9);
+        assert_eq!(counter.distinct(), 8);
+        assert_eq!(counter.count_of(2), 2);
+        assert_eq!(StoneCounter::<u64>::from_line("0 0 1").count_of(0), 2);
+    }
+
+    #[test]
+    fn test_big_counts() {
+        let mut small = StoneCounter::<u64>::from_line("125 17");
+        let mut wide = StoneCounter::<u128>::from_line("125 17");
+        let mut big = StoneCounter::<BigCount>::from_line("125 17");
+        for _ in 0..75 {
+            small.blink();
+            wide.blink();
+            big.blink();
+            assert_eq!(big.total().to_string(), small.total().to_string());
+        }
+        // well past u64, and past u128 for BigCount
+        wide.blink_n(75);
+        big.blink_n(500);
+        assert_eq!(big.generation(), 575);
+        assert!(wide.total() > u64::MAX as u128);
+        assert!(big.total().to_string().len() > 40);
+        // the closed set of values is reached long before this
+        assert_eq!(big.distinct(), 54);
+    }
+
+    #[test]
+    fn test_big_count_carries() {
+        let mut n = BigCount::one();
+        assert_eq!(BigCount::zero().to_string(), "0");
+        for _ in 0..70 {
+            let copy = n.clone();
+            n.add(&copy);
+        }
+        assert_eq!(n.to_string(), (1u128 << 70).to_string());
+        let mut m = BigCount {
+            limbs: vec![BIG_COUNT_LIMB - 1, BIG_COUNT_LIMB - 1],
+        };
+        m.add(&BigCount::one());
+        assert_eq!(m.to_string(), "1000000000000000000");
+    }
+
+    #[test]
+    fn test_rule_variants() {
+        let thirds = RuleSet::new(3, 3, 10).unwrap();
+        assert_eq!(thirds.apply(123).unwrap().as_slice(), &[1, 2, 3]);
+        assert_eq!(thirds.apply(100200).unwrap().as_slice(), &[10, 2, 0]);
+        assert_eq!(thirds.apply(12).unwrap().as_slice(), &[36]);
+        assert_eq!(thirds.apply(0).unwrap().as_slice(), &[1]);
+        let binary = RuleSet::new(3, 2, 2).unwrap();
+        // 0b1011 splits into 0b10 and 0b11, 0b111 becomes 0b10101
+        assert_eq!(binary.apply(0b1011).unwrap().as_slice(), &[0b10, 0b11]);
+        assert_eq!(binary.apply(0b111).unwrap().as_slice(), &[0b10101]);
+        assert_eq!(RuleSet::PUZZLE.apply(u64::MAX / 10), None);
+
+        let mut counter = StoneCounter::<u64>::with_rules(&[123], thirds);
+        counter.blink();
+        assert_eq!((counter.total(), counter.distinct()), (3, 3));
+
+        assert_eq!(RuleSet::new(2024, 2, 10), Ok(RuleSet::PUZZLE));
+        assert_eq!(RuleSet::new(3, 2, 1), Err(RuleError::BaseTooSmall(1)));
+        assert_eq!(RuleSet::new(3, 2, 0), Err(RuleError::BaseTooSmall(0)));
+        assert_eq!(RuleSet::new(3, 1, 10), Err(RuleError::TooFewParts(1)));
+        assert_eq!(RuleSet::new(3, 0, 10), Err(RuleError::TooFewParts(0)));
+    }
+
+    #[test]
+    fn test_closure_and_matrix() {
+        let stones = [125, 17];
+        let values = RuleSet::PUZZLE.closure(&stones, 1000).unwrap();
+        // includes the values only seen on the way in, eg 125 itself
+        assert_eq!(values.len(), 76);
+        assert!(values.contains(&125));
+        assert!(RuleSet::PUZZLE.closure(&stones, 10).is_none());
+
+        let matrix = RuleSet::PUZZLE.transition_matrix(&stones, 1000).unwrap();
+        // every value splits into at most two
+        assert!(matrix
+            .entries
+            .iter()
+            .all(|row| row.iter().sum::<u64>() <= 2));
+        let modulus = 1_000_000_007;
+        let mut counter = StoneCounter::<u128>::new(&stones);
+        for blinks in 0..150 {
+            if blinks > 30 && blinks % 20 != 0 {
+                counter.blink();
+                continue;
+            }
+            assert_eq!(
+                matrix.count_mod(&stones, blinks, modulus).unwrap() as u128,
+                counter.total() % modulus as u128,
+                "after {} blinks",
+                blinks
+            );
+            counter.blink();
+        }
+        assert_eq!(matrix.count_mod(&stones, 25, modulus), Some(55312));
+        assert_eq!(matrix.count_mod(&stones, 25, 0), None);
+        assert_eq!(matrix.count_mod(&[123457], 25, modulus), None);
+        // far past u128, checked against the decimal digits of a BigCount
+        let mut big = StoneCounter::<BigCount>::new(&stones);
+        big.blink_n(1000);
+        let expected = big
+            .total()
+            .to_string()
+            .bytes()
+            .fold(0, |acc, d| (acc * 10 + (d - b'0') as u64) % modulus);
+        assert_eq!(matrix.count_mod(&stones, 1000, modulus), Some(expected));
+        assert!(
+            matrix
+                .count_mod(&stones, 1_000_000_000_000_000_000, modulus)
+                .unwrap()
+                < modulus
         );
     }
 }
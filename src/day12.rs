@@ -1,278 +1,323 @@
-use std::collections::HashSet;
-
 use crate::util::aoc_read_day_lines;
 
-struct Land {
-    grid: Vec<Vec<char>>,
-    explored: Vec<Vec<bool>>,
-}
+const CARDINAL_DIRECTIONS: [(isize, isize); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
 
-pub fn day12_part1(test_input: Option<Vec<String>>) -> usize {
-    let grid = test_input
-        .unwrap_or(aoc_read_day_lines(12))
-        .iter()
-        .map(|line| line.chars().collect::<Vec<char>>())
-        .collect::<Vec<Vec<char>>>();
-    let mut sum = 0;
-    let width = grid[0].len();
-    let height = grid.len();
-    let mut land = Land {
-        grid,
-        explored: vec![vec![false; width]; height],
-    };
-    for y in 0..height {
-        for x in 0..width {
-            let (area, perimeter) = part1_get_area_and_perimeter(&mut land, x, y).unwrap_or((0, 0));
-            sum += perimeter * area;
-        }
-    }
-    sum
+// Which same-plant neighbours join a region. The fences are drawn around cells either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connectivity {
+    Orthogonal,
+    // also plots that only touch at a corner
+    Diagonal,
 }
 
-const CARDINAL_DIRECTIONS: [(isize, isize); 4] = [(0, 1), (1, 0), (0, -1), (-1, 0)];
+// Disjoint sets over cell indices, with path halving and union by size.
+struct UnionFind {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
 
-fn part1_get_area_and_perimeter(land: &mut Land, x: usize, y: usize) -> Option<(usize, usize)> {
-    if land.explored[y][x] {
-        return None;
+impl UnionFind {
+    fn new(n: usize) -> Self {
+        UnionFind {
+            parent: (0..n).collect(),
+            size: vec![1; n],
+        }
     }
-    let plot_type = land.grid[y][x];
-    let mut visited = vec![vec![false; land.grid[0].len()]; land.grid.len()];
 
-    Some(part1_dfs(land, x, y, &mut visited, plot_type))
-}
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
 
-fn part1_dfs(
-    land: &mut Land,
-    x: usize,
-    y: usize,
-    visited: &mut Vec<Vec<bool>>,
-    plot_type: char,
-) -> (usize, usize) {
-    visited[y][x] = true;
-    land.explored[y][x] = true;
-
-    let mut area = 1;
-    let mut perimeter = 0;
-
-    for (dx, dy) in CARDINAL_DIRECTIONS {
-        let nx = x as isize + dx;
-        let ny = y as isize + dy;
-
-        if nx < 0 || ny < 0 || nx >= land.grid[0].len() as isize || ny >= land.grid.len() as isize {
-            perimeter += 1;
-            continue;
+    fn union(&mut self, a: usize, b: usize) {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if self.size[a] < self.size[b] {
+            std::mem::swap(&mut a, &mut b);
         }
+        self.parent[b] = a;
+        self.size[a] += self.size[b];
+    }
+}
 
-        let (nx, ny) = (nx as usize, ny as usize);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoundingBox {
+    pub min_x: usize,
+    pub min_y: usize,
+    pub max_x: usize,
+    pub max_y: usize,
+}
 
-        if land.grid[ny][nx] != plot_type {
-            perimeter += 1;
-            continue;
-        }
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub label: char,
+    // (x, y) in reading order
+    pub cells: Vec<(usize, usize)>,
+    pub area: usize,
+    pub perimeter: usize,
+    pub sides: usize,
+    pub bounding_box: BoundingBox,
+    // indices of the regions entirely enclosed by this one, in reading order
+    pub holes: Vec<usize>,
+}
 
-        if visited[ny][nx] {
-            continue;
-        }
+impl Region {
+    pub fn price(&self) -> usize {
+        self.area * self.perimeter
+    }
 
-        let (sub_area, sub_perimeter) = part1_dfs(land, nx, ny, visited, plot_type);
-        area += sub_area;
-        perimeter += sub_perimeter;
+    pub fn bulk_price(&self) -> usize {
+        self.area * self.sides
     }
+}
 
-    (area, perimeter)
+// Every connected region of a garden map. Regions are numbered in reading order of their first
+// cell.
+pub struct Garden {
+    pub width: usize,
+    pub height: usize,
+    // region index per cell
+    labels: Vec<usize>,
+    regions: Vec<Region>,
 }
 
-pub fn day12_part2(test_input: Option<Vec<String>>) -> usize {
-    let grid = test_input
-        .unwrap_or(aoc_read_day_lines(12))
-        .iter()
-        .map(|line| line.chars().collect::<Vec<char>>())
-        .collect::<Vec<Vec<char>>>();
-    let mut sum = 0;
-    let width = grid[0].len();
-    let height = grid.len();
-    let mut land = Land {
-        grid,
-        explored: vec![vec![false; width]; height],
-    };
-    for y in 0..height {
-        for x in 0..width {
-            let (area, sides) = part2_get_area_and_sides(&mut land, x, y).unwrap_or((0, 0));
-            sum += sides * area;
-        }
+impl Garden {
+    pub fn from_lines(lines: &[String]) -> Self {
+        Self::new(lines, Connectivity::Orthogonal)
     }
-    sum
-}
 
-fn count_area_and_sides(land: &mut Land, start_x: usize, start_y: usize) -> (usize, usize) {
-    let plot_type = land.grid[start_y][start_x];
-    let mut visited = HashSet::new();
-    let mut area = 0;
-    let mut stack = vec![(start_x, start_y)];
-
-    let mut min_x = usize::MAX;
-    let mut max_x = usize::MIN;
-    let mut min_y = usize::MAX;
-    let mut max_y = usize::MIN;
-    // First pass: flood fill to count area and find edge
-    while let Some((x, y)) = stack.pop() {
-        if visited.contains(&(x, y)) {
-            continue;
+    pub fn new(lines: &[String], connectivity: Connectivity) -> Self {
+        let mut garden = Self::label(lines, connectivity);
+        for id in 0..garden.regions.len() {
+            garden.regions[id].holes = garden.enclosed_by(id);
         }
-        visited.insert((x, y));
-        min_x = min_x.min(x);
-        max_x = max_x.max(x);
-        min_y = min_y.min(y);
-        max_y = max_y.max(y);
-        land.explored[y][x] = true;
-        area += 1;
-
-        for (dx, dy) in CARDINAL_DIRECTIONS {
-            let nx = x as isize + dx;
-            let ny = y as isize + dy;
-            if nx < 0
-                || ny < 0
-                || nx >= land.grid[0].len() as isize
-                || ny >= land.grid.len() as isize
-            {
-                continue;
-            }
+        garden
+    }
 
-            let (nx, ny) = (nx as usize, ny as usize);
-            if is_same_plot_type(land, nx as isize, ny as isize, plot_type)
-                && !visited.contains(&(nx, ny))
-            {
-                stack.push((nx, ny));
+    // Labels every region in one scan: each cell is unioned with its already visited neighbours
+    // of the same plant, then a second scan numbers the sets and accumulates area, perimeter and
+    // corners per region. A region has exactly as many sides as corners. Holes are left empty.
+    fn label(lines: &[String], connectivity: Connectivity) -> Self {
+        let grid = lines
+            .iter()
+            .filter(|line| !line.is_empty())
+            .flat_map(|line| line.chars())
+            .collect::<Vec<char>>();
+        let height = lines.iter().filter(|line| !line.is_empty()).count();
+        let width = grid.len().checked_div(height).unwrap_or(0);
+        assert!(
+            lines
+                .iter()
+                .filter(|line| !line.is_empty())
+                .all(|line| line.chars().count() == width),
+            "map rows must all have the same width"
+        );
+
+        // neighbours that come earlier in reading order
+        let earlier: &[(isize, isize)] = match connectivity {
+            Connectivity::Orthogonal => &[(-1, 0), (0, -1)],
+            Connectivity::Diagonal => &[(-1, 0), (0, -1), (-1, -1), (1, -1)],
+        };
+        let mut sets = UnionFind::new(grid.len());
+        for y in 0..height {
+            for x in 0..width {
+                for &(dx, dy) in earlier {
+                    let (Some(nx), Some(ny)) = (x.checked_add_signed(dx), y.checked_add_signed(dy))
+                    else {
+                        continue;
+                    };
+                    if nx < width && grid[ny * width + nx] == grid[y * width + x] {
+                        sets.union(y * width + x, ny * width + nx);
+                    }
+                }
             }
         }
-    }
 
-    let wall_is_on_top = |x: usize, y: usize| {
-        if y == 0 {
-            return true;
-        }
-        if !is_same_plot_type(land, x as isize, y as isize - 1, plot_type) {
-            return true;
-        }
-        false
-    };
-    let wall_is_on_bottom = |x: usize, y: usize| {
-        if y == land.grid.len() - 1 {
-            return true;
-        }
-        if !is_same_plot_type(land, x as isize, y as isize + 1, plot_type) {
-            return true;
-        }
-        false
-    };
-    let wall_is_on_left = |x: usize, y: usize| {
-        if x == 0 {
-            return true;
-        }
-        if !is_same_plot_type(land, x as isize - 1, y as isize, plot_type) {
-            return true;
-        }
-        false
-    };
-    let wall_is_on_right = |x: usize, y: usize| {
-        if x == land.grid[0].len() - 1 {
-            return true;
-        }
-        if !is_same_plot_type(land, x as isize + 1, y as isize, plot_type) {
-            return true;
-        }
-        false
-    };
-
-    // sides are all unique combinations of:
-    // wall on top, y coordinate in range of region (deduplicate contiguous x coordinates)
-    // wall on bottom, y coordinate in range of region (deduplicate contiguous x coordinates)
-    // wall on left, x coordinate in range of region (deduplicate contiguous y coordinates)
-    // wall on right, x coordinate in range of region (deduplicate contiguous y coordinates)
-    let mut sides = 0;
-    for y in min_y..=max_y {
-        let mut cur_side = false;
-        for x in min_x..=max_x {
-            if !visited.contains(&(x, y)) {
-                cur_side = false;
-                continue;
-            }
-            if wall_is_on_top(x, y) {
-                if !cur_side {
-                    sides += 1;
+        let mut garden = Garden {
+            width,
+            height,
+            labels: vec![usize::MAX; grid.len()],
+            regions: Vec::new(),
+        };
+        // region index per set root
+        let mut region_of_root = vec![usize::MAX; grid.len()];
+        for y in 0..height {
+            for x in 0..width {
+                let root = sets.find(y * width + x);
+                if region_of_root[root] == usize::MAX {
+                    region_of_root[root] = garden.regions.len();
+                    garden.regions.push(Region {
+                        label: grid[y * width + x],
+                        cells: vec![],
+                        area: 0,
+                        perimeter: 0,
+                        sides: 0,
+                        bounding_box: BoundingBox {
+                            min_x: x,
+                            min_y: y,
+                            max_x: x,
+                            max_y: y,
+                        },
+                        holes: vec![],
+                    });
                 }
-                cur_side = true;
-            } else {
-                cur_side = false;
+                garden.labels[y * width + x] = region_of_root[root];
             }
         }
-    }
-    for y in min_y..=max_y {
-        let mut cur_side = false;
-        for x in min_x..=max_x {
-            if !visited.contains(&(x, y)) {
-                cur_side = false;
-                continue;
-            }
-            if wall_is_on_bottom(x, y) {
-                if !cur_side {
-                    sides += 1;
-                }
-                cur_side = true;
-            } else {
-                cur_side = false;
+
+        for y in 0..height {
+            for x in 0..width {
+                let id = garden.labels[y * width + x];
+                let same = |dx, dy| garden.label_at(x, y, dx, dy) == Some(id);
+                let perimeter = CARDINAL_DIRECTIONS
+                    .iter()
+                    .filter(|&&(dx, dy)| !same(dx, dy))
+                    .count();
+                // convex where both sides of the corner are fenced, concave where both sides
+                // are open but the diagonal is not
+                let corners = [(-1, -1), (1, -1), (1, 1), (-1, 1)]
+                    .iter()
+                    .filter(|&&(dx, dy)| {
+                        let (horizontal, vertical) = (same(dx, 0), same(0, dy));
+                        (!horizontal && !vertical) || (horizontal && vertical && !same(dx, dy))
+                    })
+                    .count();
+                let region = &mut garden.regions[id];
+                region.cells.push((x, y));
+                region.area += 1;
+                region.perimeter += perimeter;
+                region.sides += corners;
+                let bb = &mut region.bounding_box;
+                bb.min_x = bb.min_x.min(x);
+                bb.max_x = bb.max_x.max(x);
+                bb.max_y = y;
             }
         }
+        garden
     }
-    for x in min_x..=max_x {
-        let mut cur_side = false;
-        for y in min_y..=max_y {
-            if !visited.contains(&(x, y)) {
-                cur_side = false;
-                continue;
-            }
-            if wall_is_on_left(x, y) {
-                if !cur_side {
-                    sides += 1;
+
+    // Region index of the cell at (x + dx, y + dy), None outside the map.
+    fn label_at(&self, x: usize, y: usize, dx: isize, dy: isize) -> Option<usize> {
+        let nx = x.checked_add_signed(dx).filter(|&nx| nx < self.width)?;
+        let ny = y.checked_add_signed(dy).filter(|&ny| ny < self.height)?;
+        Some(self.labels[ny * self.width + nx])
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    pub fn region_at(&self, x: usize, y: usize) -> usize {
+        self.labels[y * self.width + x]
+    }
+
+    // Regions that cannot reach the edge of the map without crossing region `id`. Found by
+    // flooding everything outside the region from a ring just outside its bounding box; whatever
+    // the flood does not reach is enclosed.
+    fn enclosed_by(&self, id: usize) -> Vec<usize> {
+        let bb = self.regions[id].bounding_box;
+        // the bounding box plus a one cell ring, shifted so that the ring starts at 0
+        let (w, h) = (bb.max_x - bb.min_x + 3, bb.max_y - bb.min_y + 3);
+        let inside = |bx: usize, by: usize| {
+            bx > 0
+                && by > 0
+                && bx < w - 1
+                && by < h - 1
+                && self.labels[(by - 1 + bb.min_y) * self.width + bx - 1 + bb.min_x] == id
+        };
+        let mut reached = vec![false; w * h];
+        let mut stack = vec![(0usize, 0usize)];
+        reached[0] = true;
+        while let Some((bx, by)) = stack.pop() {
+            for (dx, dy) in CARDINAL_DIRECTIONS {
+                let (Some(nx), Some(ny)) = (bx.checked_add_signed(dx), by.checked_add_signed(dy))
+                else {
+                    continue;
+                };
+                if nx < w && ny < h && !reached[ny * w + nx] && !inside(nx, ny) {
+                    reached[ny * w + nx] = true;
+                    stack.push((nx, ny));
                 }
-                cur_side = true;
-            } else {
-                cur_side = false;
             }
         }
+        let mut holes = (1..h - 1)
+            .flat_map(|by| (1..w - 1).map(move |bx| (bx, by)))
+            .filter(|&(bx, by)| !reached[by * w + bx] && !inside(bx, by))
+            .map(|(bx, by)| self.labels[(by - 1 + bb.min_y) * self.width + bx - 1 + bb.min_x])
+            .collect::<Vec<_>>();
+        holes.sort_unstable();
+        holes.dedup();
+        holes
     }
-    for x in min_x..=max_x {
-        let mut cur_side = false;
-        for y in min_y..=max_y {
-            if !visited.contains(&(x, y)) {
-                cur_side = false;
-                continue;
-            }
-            if wall_is_on_right(x, y) {
-                if !cur_side {
-                    sides += 1;
-                }
-                cur_side = true;
-            } else {
-                cur_side = false;
+
+    // The region drawn over its bounding box on a (2w+1) x (2h+1) grid: cells at odd
+    // coordinates hold the label, the lines between them hold '-' and '|' where the fence runs
+    // and '+' where it turns.
+    pub fn render_outline(&self, id: usize) -> String {
+        let bb = self.regions[id].bounding_box;
+        let (w, h) = (bb.max_x - bb.min_x + 1, bb.max_y - bb.min_y + 1);
+        // whether the cell at bounding box coordinates (bx, by) belongs to the region
+        let member = |bx: isize, by: isize| {
+            bx >= 0
+                && by >= 0
+                && (bx as usize) < w
+                && (by as usize) < h
+                && self.region_at(bb.min_x + bx as usize, bb.min_y + by as usize) == id
+        };
+        let fence_below = |bx: isize, by: isize| member(bx, by) != member(bx, by + 1);
+        let fence_right = |bx: isize, by: isize| member(bx, by) != member(bx + 1, by);
+        let mut out = Vec::with_capacity(2 * h + 1);
+        for ry in 0..2 * h as isize + 1 {
+            let mut line = String::with_capacity(2 * w + 1);
+            for rx in 0..2 * w as isize + 1 {
+                // cell coordinates of the cell at or up-left of this position
+                let (bx, by) = ((rx - 1).div_euclid(2), (ry - 1).div_euclid(2));
+                let c = match (rx % 2 == 1, ry % 2 == 1) {
+                    (true, true) if member(bx, by) => self.regions[id].label,
+                    (true, true) => ' ',
+                    (true, false) if fence_below(bx, by) => '-',
+                    (false, true) if fence_right(bx, by) => '|',
+                    (false, false) => {
+                        let horizontal = fence_below(bx, by) || fence_below(bx + 1, by);
+                        let vertical = fence_right(bx, by) || fence_right(bx, by + 1);
+                        match (horizontal, vertical) {
+                            (true, true) => '+',
+                            (true, false) => '-',
+                            (false, true) => '|',
+                            (false, false) => ' ',
+                        }
+                    }
+                    _ => ' ',
+                };
+                line.push(c);
             }
+            out.push(line.trim_end().to_string());
         }
+        out.join("\n")
     }
-
-    (area, sides)
 }
 
-fn is_same_plot_type(land: &Land, x: isize, y: isize, cur_plot_type: char) -> bool {
-    land.grid[y as usize][x as usize] == cur_plot_type
+pub fn day12_part1(test_input: Option<Vec<String>>) -> usize {
+    let lines = test_input.unwrap_or_else(|| aoc_read_day_lines(12));
+    Garden::label(&lines, Connectivity::Orthogonal)
+        .regions()
+        .iter()
+        .map(|r| r.price())
+        .sum()
 }
 
-fn part2_get_area_and_sides(land: &mut Land, x: usize, y: usize) -> Option<(usize, usize)> {
-    if land.explored[y][x] {
-        return None;
-    }
-    let (area, sides) = count_area_and_sides(land, x, y);
-    land.explored[y][x] = true;
-    Some((area, sides))
+pub fn day12_part2(test_input: Option<Vec<String>>) -> usize {
+    let lines = test_input.unwrap_or_else(|| aoc_read_day_lines(12));
+    Garden::label(&lines, Connectivity::Orthogonal)
+        .regions()
+        .iter()
+        .map(|r| r.bulk_price())
+        .sum()
 }
 
 #[cfg(test)]
@@ -303,4 +348,120 @@ MMMISSJEEE";
             1206
         );
     }
+
+    fn lines(s: &str) -> Vec<String> {
+        s.lines().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_regions() {
+        let garden = Garden::from_lines(&lines(EXAMPLE));
+        let regions = garden.regions();
+        assert_eq!(regions.len(), 11);
+        assert_eq!(regions.iter().map(|r| r.price()).sum::<usize>(), 1930);
+        assert_eq!(regions.iter().map(|r| r.bulk_price()).sum::<usize>(), 1206);
+        let r = &regions[0];
+        assert_eq!((r.label, r.area, r.perimeter, r.sides), ('R', 12, 18, 10));
+        assert_eq!(
+            r.bounding_box,
+            BoundingBox {
+                min_x: 0,
+                min_y: 0,
+                max_x: 4,
+                max_y: 3
+            }
+        );
+        assert_eq!(r.cells.len(), r.area);
+        assert_eq!(garden.region_at(2, 3), 0);
+        // the two C regions are counted separately
+        assert_eq!(regions.iter().filter(|r| r.label == 'C').count(), 2);
+    }
+
+    #[test]
+    fn test_holes() {
+        let garden = Garden::from_lines(&lines(
+            "OOOOO
+OXOXO
+OOOOO
+OXOXO
+OOOOO",
+        ));
+        let regions = garden.regions();
+        assert_eq!(regions[0].holes, vec![1, 2, 3, 4]);
+        assert_eq!((regions[0].perimeter, regions[0].sides), (36, 20));
+        assert!(regions[1].holes.is_empty());
+
+        // both B regions sit inside A, touching only diagonally
+        let garden = Garden::from_lines(&lines(
+            "AAAAAA
+AAABBA
+AAABBA
+ABBAAA
+ABBAAA
+AAAAAA",
+        ));
+        assert_eq!(garden.regions()[0].holes, vec![1, 2]);
+        assert_eq!(garden.regions()[0].sides, 12);
+        // E touches the map edge, so it is not enclosed
+        let garden = Garden::from_lines(&lines("AAE\nAXA\nAAA"));
+        assert_eq!(garden.regions()[0].holes, vec![2]);
+    }
+
+    #[test]
+    fn test_render_outline() {
+        let garden = Garden::from_lines(&lines(
+            "AAAA
+BBCD
+BBCC
+EEEC",
+        ));
+        assert_eq!(
+            garden.render_outline(0),
+            "+-------+
+|A A A A|
++-------+"
+        );
+        assert_eq!(
+            garden.render_outline(2),
+            "+-+
+|C|
+| +-+
+|C C|
++-+ |
+  |C|
+  +-+"
+        );
+    }
+
+    #[test]
+    fn test_diagonal_connectivity() {
+        let lines = lines(
+            "AB
+BA",
+        );
+        let orthogonal = Garden::new(&lines, Connectivity::Orthogonal);
+        assert_eq!(orthogonal.regions().len(), 4);
+        let diagonal = Garden::new(&lines, Connectivity::Diagonal);
+        assert_eq!(diagonal.regions().len(), 2);
+        let a = &diagonal.regions()[0];
+        assert_eq!((a.label, a.area, a.perimeter, a.sides), ('A', 2, 8, 8));
+        assert_eq!(a.cells, vec![(0, 0), (1, 1)]);
+
+        // the two B squares inside A touch at a corner
+        let lines = super::tests::lines(
+            "AAAAAA
+AAABBA
+AAABBA
+ABBAAA
+ABBAAA
+AAAAAA",
+        );
+        let diagonal = Garden::new(&lines, Connectivity::Diagonal);
+        assert_eq!(diagonal.regions().len(), 2);
+        assert_eq!(diagonal.regions()[0].holes, vec![1]);
+        assert_eq!(
+            (diagonal.regions()[1].area, diagonal.regions()[1].sides),
+            (8, 8)
+        );
+    }
 }
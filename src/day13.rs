@@ -1,133 +1,478 @@
 use crate::util::aoc_read_day_lines;
 
-#[derive(Debug)]
-struct Button {
-    x: usize,
-    y: usize,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Button {
+    pub x: usize,
+    pub y: usize,
+    // tokens per press
+    pub cost: usize,
+    // most presses allowed, if limited
+    pub limit: Option<usize>,
 }
 
-#[derive(Debug)]
-struct WinCondition {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WinCondition {
     pub x: usize,
     pub y: usize,
 }
 
-#[derive(Debug)]
-struct Game {
-    pub a: Button,
-    pub b: Button,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Game {
+    pub buttons: Vec<Button>,
     pub prize: WinCondition,
 }
 
+impl Game {
+    pub fn cost(&self, presses: &[usize]) -> usize {
+        self.buttons
+            .iter()
+            .zip(presses)
+            .map(|(button, &n)| button.cost * n)
+            .sum()
+    }
+}
+
+// Why no press vector wins a prize.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Infeasibility {
+    // no integer press counts reach the prize, even allowing negative ones
+    NoSolution,
+    // integer solutions exist, but none with every button pressed 0..=limit times
+    ExceedsLimits,
+    // too many press combinations of the buttons beyond the first three to try them all
+    SearchTooLarge,
+}
+
+// Most combinations of extra button presses `solve` will enumerate.
+const MAX_COMBINATIONS: i128 = 1_000_000;
+
 const MOVE_COST_A: usize = 3;
 const MOVE_COST_B: usize = 1;
 
-fn solve(game: &Game) -> Option<usize> {
-    let a_x = game.a.x as f64;
-    let a_y = game.a.y as f64;
-    let b_x = game.b.x as f64;
-    let b_y = game.b.y as f64;
-    let prize_x = game.prize.x as f64;
-    let prize_y = game.prize.y as f64;
-
-    // Equations:
-    // #1: n*aX + m*bX = prizeX
-    // #2: n*aY + m*bY = prizeY
-
-    // From equation #1:
-    // n*aX + m*bX = prizeX
-    // m*bX = prizeX - n*aX
-    // #3: m = (prizeX - n*aX) / bX
-
-    // Substitute #3 into #2:
-    // n*aY + ((prizeX - n*aX) / bX) * bY = prizeY
-    // n*aY + prizeX*bY/bX - n*aX*bY/bX = prizeY
-    // n*(aY - aX*bY/bX) = prizeY - prizeX*bY/bX
-    // n = (prizeY*bX - prizeX*bY) / (aY*bX - aX*bY)
-
-    // Check if denominator is 0
-    let denominator = a_y * b_x - a_x * b_y;
-    if denominator == 0.0 {
-        return None;
+// (g, s, t) with a*s + b*t = g = gcd(a, b), g >= 0
+fn extended_gcd(a: i128, b: i128) -> (i128, i128, i128) {
+    if b == 0 {
+        (a.abs(), a.signum(), 0)
+    } else {
+        let (g, s, t) = extended_gcd(b, a.rem_euclid(b));
+        (g, t, s - a.div_euclid(b) * t)
+    }
+}
+
+fn div_floor(a: i128, b: i128) -> i128 {
+    let q = a / b;
+    if (a % b != 0) && ((a < 0) != (b < 0)) {
+        q - 1
+    } else {
+        q
+    }
+}
+
+fn div_ceil(a: i128, b: i128) -> i128 {
+    -div_floor(-a, b)
+}
+
+// The range of k keeping `base + k*step` within 0..=limit, None if there is no such k. An end is
+// None when it is unbounded.
+fn k_range(base: i128, step: i128, limit: Option<i128>) -> Option<(Option<i128>, Option<i128>)> {
+    let in_range = base >= 0 && limit.is_none_or(|limit| base <= limit);
+    if step == 0 {
+        return in_range.then_some((None, None));
     }
-    let numerator = prize_y * b_x - prize_x * b_y;
+    if step < 0 {
+        // the same range for -k
+        let (lo, hi) = k_range(base, -step, limit)?;
+        return Some((hi.map(|k| -k), lo.map(|k| -k)));
+    }
+    let lo = div_ceil(-base, step);
+    let hi = limit.map(|limit| div_floor(limit - base, step));
+    Some((Some(lo), hi))
+}
 
-    let n = numerator / denominator;
+// The k within every range, as (lo, hi), or None if the ranges do not overlap.
+fn intersect_ranges(
+    ranges: &[Option<(Option<i128>, Option<i128>)>],
+) -> Option<(Option<i128>, Option<i128>)> {
+    let mut lo = None;
+    let mut hi = None;
+    for &range in ranges {
+        let (range_lo, range_hi) = range?;
+        lo = lo.max(range_lo);
+        hi = [hi, range_hi].into_iter().flatten().min();
+    }
+    match (lo, hi) {
+        (Some(lo), Some(hi)) if lo > hi => None,
+        _ => Some((lo, hi)),
+    }
+}
 
-    // Calculate m using equation (3)
-    let m = (prize_x - n * a_x) / b_x;
+// The end of a range minimising a cost with this slope in k.
+fn cheapest_end((lo, hi): (Option<i128>, Option<i128>), slope: i128) -> i128 {
+    match (slope >= 0, lo, hi) {
+        (true, Some(k), _) | (false, _, Some(k)) => k,
+        // costs are non-negative, so the cost cannot decrease forever in an open direction
+        (_, Some(k), None) | (_, None, Some(k)) => k,
+        (_, None, None) => unreachable!("presses are bounded below by zero"),
+    }
+}
 
-    // Check if n and m are non-negative
-    if n < 0.0 || m < 0.0 {
+// The solutions of a*c = b (mod m) as c = r (mod modulus).
+fn solve_congruence(a: i128, b: i128, m: i128) -> Option<(i128, i128)> {
+    let (g, s, _) = extended_gcd(a, m);
+    if b % g != 0 {
         return None;
     }
+    let modulus = m / g;
+    Some(((s * (b / g)).rem_euclid(modulus), modulus))
+}
 
-    // Verify n and m are integers
-    if n % 1.0 != 0.0 || m % 1.0 != 0.0 {
+// c = r1 (mod m1) and c = r2 (mod m2) combined into one congruence.
+fn combine_congruences((r1, m1): (i128, i128), (r2, m2): (i128, i128)) -> Option<(i128, i128)> {
+    let (g, s, _) = extended_gcd(m1, m2);
+    if (r2 - r1) % g != 0 {
         return None;
     }
+    let lcm = m1 / g * m2;
+    let k = ((r2 - r1) / g * s).rem_euclid(m2 / g);
+    Some(((r1 + m1 * k).rem_euclid(lcm), lcm))
+}
 
-    // Convert back to usize for final cost calculation
-    let n = n as usize;
-    let m = m as usize;
-    Some(MOVE_COST_A * n + MOVE_COST_B * m)
+// A button reduced to what the pair solver needs.
+#[derive(Clone, Copy)]
+struct Axis {
+    x: i128,
+    y: i128,
+    cost: i128,
+    limit: Option<i128>,
 }
 
-pub fn day13_part1() -> usize {
-    let lines = aoc_read_day_lines(13);
-    let empty_lines_removed = lines
+impl Axis {
+    fn in_range(&self, n: i128) -> bool {
+        n >= 0 && self.limit.is_none_or(|limit| n <= limit)
+    }
+}
+
+// Cheapest (n, m) with n*a + m*b = (tx, ty).
+fn solve_pair(a: Axis, b: Axis, (tx, ty): (i128, i128)) -> Result<(i128, i128), Infeasibility> {
+    let det = a.x * b.y - a.y * b.x;
+    if det != 0 {
+        // Cramer's rule, exact in integers
+        let n_num = tx * b.y - ty * b.x;
+        let m_num = a.x * ty - a.y * tx;
+        if n_num % det != 0 || m_num % det != 0 {
+            return Err(Infeasibility::NoSolution);
+        }
+        let (n, m) = (n_num / det, m_num / det);
+        return if a.in_range(n) && b.in_range(m) {
+            Ok((n, m))
+        } else {
+            Err(Infeasibility::ExceedsLimits)
+        };
+    }
+
+    // Both buttons move along the same line, which the prize has to be on. Project everything
+    // onto an axis where a moves, then solve a1*n + b1*m = t1 over the integers.
+    if a.x * ty - a.y * tx != 0 || b.x * ty - b.y * tx != 0 {
+        return Err(Infeasibility::NoSolution);
+    }
+    let (a1, b1, t1) = if a.x != 0 {
+        (a.x, b.x, tx)
+    } else {
+        (a.y, b.y, ty)
+    };
+    let (g, s, t) = extended_gcd(a1, b1);
+    if t1 % g != 0 {
+        return Err(Infeasibility::NoSolution);
+    }
+    // every solution is n = n0 + k*step_n, m = m0 - k*step_m
+    let (n0, m0) = (s * (t1 / g), t * (t1 / g));
+    let (step_n, step_m) = (b1 / g, a1 / g);
+
+    let range = intersect_ranges(&[k_range(n0, step_n, a.limit), k_range(m0, -step_m, b.limit)])
+        .ok_or(Infeasibility::ExceedsLimits)?;
+    // the cost is linear in k, so the cheapest solution is at one end of the range
+    let k = cheapest_end(range, a.cost * step_n - b.cost * step_m);
+    Ok((n0 + k * step_n, m0 - k * step_m))
+}
+
+// Cheapest (c, n, m) with c*e + n*a + m*b = (tx, ty), for a and b not collinear. For a given c
+// Cramer's rule fixes n and m, and they are integers only for c in one residue class mod the
+// determinant. Over that class n, m and the cost are all linear, so the cheapest c is again at one
+// end of the range where every count is within its limits.
+fn solve_triple(
+    a: Axis,
+    b: Axis,
+    e: Axis,
+    (tx, ty): (i128, i128),
+) -> Result<(i128, i128, i128), Infeasibility> {
+    let det = a.x * b.y - a.y * b.x;
+    // n*det = n_num - c*u and m*det = m_num - c*v
+    let (n_num, u) = (tx * b.y - ty * b.x, e.x * b.y - e.y * b.x);
+    let (m_num, v) = (a.x * ty - a.y * tx, a.x * e.y - a.y * e.x);
+    let (c0, period) = solve_congruence(u, n_num, det.abs())
+        .zip(solve_congruence(v, m_num, det.abs()))
+        .and_then(|(cn, cm)| combine_congruences(cn, cm))
+        .ok_or(Infeasibility::NoSolution)?;
+    // c = c0 + k*period, n = n0 + k*step_n, m = m0 + k*step_m
+    let (n0, step_n) = ((n_num - c0 * u) / det, -u * period / det);
+    let (m0, step_m) = ((m_num - c0 * v) / det, -v * period / det);
+    let range = intersect_ranges(&[
+        k_range(c0, period, e.limit),
+        k_range(n0, step_n, a.limit),
+        k_range(m0, step_m, b.limit),
+    ])
+    .ok_or(Infeasibility::ExceedsLimits)?;
+    let k = cheapest_end(range, e.cost * period + a.cost * step_n + b.cost * step_m);
+    Ok((c0 + k * period, n0 + k * step_n, m0 + k * step_m))
+}
+
+// The cheapest press count per button that wins the prize. Buttons that do not move the claw are
+// never pressed. Two buttons are solved exactly, and so is a third when the first two are not
+// collinear. Any further buttons are enumerated within their bounds, up to MAX_COMBINATIONS
+// combinations, with the others solved exactly for each.
+pub fn solve(game: &Game) -> Result<Vec<usize>, Infeasibility> {
+    let axes = game
+        .buttons
         .iter()
-        .filter(|line| !line.trim().is_empty())
+        .map(|b| Axis {
+            x: b.x as i128,
+            y: b.y as i128,
+            cost: b.cost as i128,
+            limit: b.limit.map(|l| l as i128),
+        })
+        .collect::<Vec<_>>();
+    let moving = (0..axes.len())
+        .filter(|&i| axes[i].x != 0 || axes[i].y != 0)
+        .collect::<Vec<_>>();
+    let target = (game.prize.x as i128, game.prize.y as i128);
+    let mut presses = vec![0; axes.len()];
+
+    match moving.as_slice() {
+        [] if target == (0, 0) => return Ok(presses),
+        [] => return Err(Infeasibility::NoSolution),
+        &[i] => {
+            // pair the button with a copy of itself that is never worth pressing
+            let never = Axis {
+                limit: Some(0),
+                ..axes[i]
+            };
+            let (n, _) = solve_pair(axes[i], never, target)?;
+            presses[i] = n as usize;
+            return Ok(presses);
+        }
+        _ => {}
+    }
+
+    // prefer a pair that is not collinear, so that each enumerated combination has one solution
+    let (p, q) = moving
+        .iter()
+        .enumerate()
+        .flat_map(|(n, &i)| moving[n + 1..].iter().map(move |&j| (i, j)))
+        .find(|&(i, j)| axes[i].x * axes[j].y != axes[i].y * axes[j].x)
+        .unwrap_or((moving[0], moving[1]));
+    let mut extra = moving
+        .iter()
+        .copied()
+        .filter(|&i| i != p && i != q)
+        .collect::<Vec<_>>();
+    // buttons only move the claw forward, so none can be pressed more often than it takes to
+    // pass the prize on some axis
+    let mut bounds = extra
+        .iter()
+        .map(|&i| {
+            let a = axes[i];
+            let by_target = [(a.x, target.0), (a.y, target.1)]
+                .into_iter()
+                .filter(|&(d, _)| d > 0)
+                .map(|(d, t)| t / d)
+                .min()
+                .unwrap();
+            a.limit.map_or(by_target, |limit| limit.min(by_target))
+        })
         .collect::<Vec<_>>();
-    let mut games = Vec::new();
-    // parse 3 lines at a time
-    for i in (0..empty_lines_removed.len()).step_by(3) {
-        games.push(parse_game(&empty_lines_removed[i..i + 3]));
+    // the extra button with the most possible presses is solved exactly if it can be
+    let collinear = axes[p].x * axes[q].y == axes[p].y * axes[q].x;
+    let exact = (0..extra.len())
+        .max_by_key(|&n| bounds[n])
+        .filter(|_| !collinear)
+        .map(|n| {
+            bounds.remove(n);
+            extra.remove(n)
+        });
+    let combinations = bounds
+        .iter()
+        .try_fold(1i128, |product, &bound| product.checked_mul(bound + 1))
+        .filter(|&n| n <= MAX_COMBINATIONS);
+    if combinations.is_none() {
+        return Err(Infeasibility::SearchTooLarge);
+    }
+
+    let mut best: Option<(i128, Vec<i128>)> = None;
+    let mut worst_error = Infeasibility::NoSolution;
+    let mut counts = vec![0i128; extra.len()];
+    loop {
+        let (mut tx, mut ty) = target;
+        let mut cost = 0;
+        for (&i, &n) in extra.iter().zip(counts.iter()) {
+            tx -= axes[i].x * n;
+            ty -= axes[i].y * n;
+            cost += axes[i].cost * n;
+        }
+        let solved = match exact {
+            Some(e) => solve_triple(axes[p], axes[q], axes[e], (tx, ty))
+                .map(|(c, n, m)| (axes[e].cost * c, vec![c, n, m])),
+            None => solve_pair(axes[p], axes[q], (tx, ty)).map(|(n, m)| (0, vec![n, m])),
+        };
+        match solved {
+            Ok((exact_cost, solved)) => {
+                let (n, m) = (solved[solved.len() - 2], solved[solved.len() - 1]);
+                let cost = cost + exact_cost + axes[p].cost * n + axes[q].cost * m;
+                if best.as_ref().is_none_or(|(c, _)| cost < *c) {
+                    let mut all = counts.clone();
+                    all.extend(solved);
+                    best = Some((cost, all));
+                }
+            }
+            Err(Infeasibility::ExceedsLimits) => worst_error = Infeasibility::ExceedsLimits,
+            Err(_) => {}
+        }
+        // next combination of extra presses, like an odometer
+        let Some(pos) = (0..counts.len()).find(|&pos| counts[pos] < bounds[pos]) else {
+            break;
+        };
+        counts[pos] += 1;
+        counts[..pos].iter_mut().for_each(|c| *c = 0);
+    }
+
+    let (_, all) = best.ok_or(worst_error)?;
+    for (&i, &n) in extra
+        .iter()
+        .chain(exact.iter())
+        .chain([p, q].iter())
+        .zip(all.iter())
+    {
+        presses[i] = n as usize;
+    }
+    Ok(presses)
+}
+
+// Per machine outcome for a batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineReport {
+    // presses per button, all zero when the prize cannot be won
+    pub presses: Vec<usize>,
+    pub cost: usize,
+    // why the prize cannot be won, None when it can
+    pub reason: Option<Infeasibility>,
+}
+
+impl MachineReport {
+    pub fn winnable(&self) -> bool {
+        self.reason.is_none()
+    }
+}
+
+impl Game {
+    // The same machine with the prize moved `offset` further along both axes.
+    pub fn with_offset(&self, offset: usize) -> Game {
+        Game {
+            buttons: self.buttons.clone(),
+            prize: WinCondition {
+                x: self.prize.x + offset,
+                y: self.prize.y + offset,
+            },
+        }
+    }
+
+    // The same machine with every button limited to `limit` presses on top of its own limit.
+    pub fn with_limit(&self, limit: Option<usize>) -> Game {
+        let mut game = self.clone();
+        for button in game.buttons.iter_mut() {
+            button.limit = match (button.limit, limit) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+        }
+        game
     }
+
+    pub fn report(&self) -> MachineReport {
+        match solve(self) {
+            Ok(presses) => MachineReport {
+                cost: self.cost(&presses),
+                presses,
+                reason: None,
+            },
+            Err(reason) => MachineReport {
+                presses: vec![0; self.buttons.len()],
+                cost: 0,
+                reason: Some(reason),
+            },
+        }
+    }
+}
+
+pub const PART2_OFFSET: usize = 10000000000000;
+
+// Reports for every machine with the prizes moved by `offset` and presses limited to `limit`.
+pub fn report(games: &[Game], offset: usize, limit: Option<usize>) -> Vec<MachineReport> {
     games
         .iter()
-        .filter_map(|g| solve(g))
-        .reduce(|a, b| a + b)
-        .unwrap()
+        .map(|g| g.with_offset(offset).with_limit(limit).report())
+        .collect()
+}
+
+// Tokens needed to win every winnable prize; 0 when there are none.
+pub fn total_cost(games: &[Game], offset: usize, limit: Option<usize>) -> usize {
+    report(games, offset, limit).iter().map(|r| r.cost).sum()
 }
 
-pub fn day13_part2() -> usize {
-    let lines = aoc_read_day_lines(13);
+// Machines are three lines each, blank lines in between are ignored.
+pub fn parse_games(lines: &[String]) -> Vec<Game> {
     let empty_lines_removed = lines
         .iter()
         .filter(|line| !line.trim().is_empty())
         .collect::<Vec<_>>();
-    let mut games = Vec::new();
-    // parse 3 lines at a time
-    for i in (0..empty_lines_removed.len()).step_by(3) {
-        games.push(parse_game(&empty_lines_removed[i..i + 3]));
-    }
-    for g in games.iter_mut() {
-        g.prize.x += 10000000000000;
-        g.prize.y += 10000000000000;
-    }
-    games
-        .iter()
-        .filter_map(|g| solve(g))
-        .reduce(|a, b| a + b)
-        .unwrap()
+    empty_lines_removed.chunks(3).map(parse_game).collect()
+}
+
+pub fn day13(test_input: Option<Vec<String>>, offset: usize, limit: Option<usize>) -> usize {
+    let lines = test_input.unwrap_or_else(|| aoc_read_day_lines(13));
+    total_cost(&parse_games(&lines), offset, limit)
+}
+
+pub fn day13_part1(test_input: Option<Vec<String>>) -> usize {
+    day13(test_input, 0, Some(100))
+}
+
+pub fn day13_part2(test_input: Option<Vec<String>>) -> usize {
+    day13(test_input, PART2_OFFSET, None)
+}
+
+// Both parts from a single parse.
+pub fn day13_both(test_input: Option<Vec<String>>) -> (usize, usize) {
+    let lines = test_input.unwrap_or_else(|| aoc_read_day_lines(13));
+    let games = parse_games(&lines);
+    (
+        total_cost(&games, 0, Some(100)),
+        total_cost(&games, PART2_OFFSET, None),
+    )
 }
 
 fn parse_game(lines: &[&String]) -> Game {
     let button_a_line = lines[0];
     let button_b_line = lines[1];
     let win_condition_line = lines[2];
-    let button_a = parse_button(button_a_line);
-    let button_b = parse_button(button_b_line);
+    let button_a = parse_button(button_a_line, MOVE_COST_A);
+    let button_b = parse_button(button_b_line, MOVE_COST_B);
     let win_condition = parse_win_condition(win_condition_line);
     Game {
-        a: button_a,
-        b: button_b,
+        buttons: vec![button_a, button_b],
         prize: win_condition,
     }
 }
 
-fn parse_button(line: &str) -> Button {
+fn parse_button(line: &str, cost: usize) -> Button {
     let after_colon = line.split(":").nth(1).unwrap();
     let xy = after_colon
         .split(",")
@@ -145,7 +490,12 @@ fn parse_button(line: &str) -> Button {
         .collect::<Vec<_>>();
     let x = xy[0];
     let y = xy[1];
-    Button { x, y }
+    Button {
+        x,
+        y,
+        cost,
+        limit: None,
+    }
 }
 
 fn parse_win_condition(line: &str) -> WinCondition {
@@ -168,3 +518,240 @@ fn parse_win_condition(line: &str) -> WinCondition {
     let y = xy[1];
     WinCondition { x, y }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_rng;
+
+    const EXAMPLE: &str = "Button A: X+94, Y+34
+Button B: X+22, Y+67
+Prize: X=8400, Y=5400
+
+Button A: X+26, Y+66
+Button B: X+67, Y+21
+Prize: X=12748, Y=12176
+
+Button A: X+17, Y+86
+Button B: X+84, Y+37
+Prize: X=7870, Y=6450
+
+Button A: X+69, Y+23
+Button B: X+27, Y+71
+Prize: X=18641, Y=10279";
+
+    fn example() -> Vec<String> {
+        EXAMPLE.lines().map(|s| s.to_string()).collect()
+    }
+
+    fn button(x: usize, y: usize, cost: usize, limit: Option<usize>) -> Button {
+        Button { x, y, cost, limit }
+    }
+
+    fn game(buttons: Vec<Button>, x: usize, y: usize) -> Game {
+        Game {
+            buttons,
+            prize: WinCondition { x, y },
+        }
+    }
+
+    #[test]
+    fn test_day13_part1() {
+        assert_eq!(day13_part1(Some(example())), 480);
+    }
+
+    #[test]
+    fn test_day13_part2() {
+        assert_eq!(day13_part2(Some(example())), 875318608908);
+    }
+
+    #[test]
+    fn test_press_limits() {
+        let games = parse_games(&example())
+            .iter()
+            .map(|g| g.with_limit(Some(100)))
+            .collect::<Vec<_>>();
+        assert_eq!(solve(&games[0]), Ok(vec![80, 40]));
+        assert_eq!(solve(&games[1]), Err(Infeasibility::NoSolution));
+        // 80 presses of A are needed, which a limit of 79 forbids
+        let mut limited = games[0].clone();
+        limited.buttons[0].limit = Some(79);
+        assert_eq!(solve(&limited), Err(Infeasibility::ExceedsLimits));
+        // a batch limit does not lift or loosen a button's own limit
+        assert_eq!(limited.with_limit(None).buttons[0].limit, Some(79));
+        assert_eq!(limited.with_limit(Some(200)).buttons[0].limit, Some(79));
+        assert_eq!(limited.with_limit(Some(50)).buttons[0].limit, Some(50));
+        assert_eq!(
+            report(&[limited.clone()], 0, None)[0].reason,
+            Some(Infeasibility::ExceedsLimits)
+        );
+    }
+
+    #[test]
+    fn test_collinear_buttons() {
+        // B moves twice as far as A for less than twice the cost, so use as many B as possible
+        let g = game(vec![button(1, 1, 3, None), button(2, 2, 5, None)], 7, 7);
+        assert_eq!(solve(&g), Ok(vec![1, 3]));
+        let g = game(vec![button(1, 1, 3, None), button(2, 2, 7, None)], 7, 7);
+        assert_eq!(solve(&g), Ok(vec![7, 0]));
+        let g = game(vec![button(1, 1, 3, None), button(2, 2, 5, Some(2))], 7, 7);
+        assert_eq!(solve(&g), Ok(vec![3, 2]));
+        let g = game(vec![button(2, 2, 3, None), button(4, 4, 5, None)], 7, 7);
+        assert_eq!(solve(&g), Err(Infeasibility::NoSolution));
+        let g = game(vec![button(1, 1, 3, None), button(2, 2, 5, None)], 7, 8);
+        assert_eq!(solve(&g), Err(Infeasibility::NoSolution));
+        // only one button actually moves
+        let g = game(vec![button(0, 0, 1, None), button(3, 0, 1, Some(2))], 9, 0);
+        assert_eq!(solve(&g), Err(Infeasibility::ExceedsLimits));
+        let g = game(vec![button(0, 0, 1, None), button(3, 0, 1, None)], 9, 0);
+        assert_eq!(solve(&g), Ok(vec![0, 3]));
+    }
+
+    #[test]
+    fn test_many_buttons() {
+        // a third, cheap diagonal button beats the puzzle's two
+        let mut g = parse_games(&example()).remove(0);
+        g.buttons.push(button(1, 1, 0, Some(400)));
+        let presses = solve(&g).unwrap();
+        let reached = g
+            .buttons
+            .iter()
+            .zip(presses.iter())
+            .fold((0, 0), |(x, y), (b, &n)| (x + b.x * n, y + b.y * n));
+        assert_eq!(reached, (8400, 5400));
+        assert!(g.cost(&presses) < 280);
+
+        let buttons = |c_cost| {
+            vec![
+                button(3, 0, 1, None),
+                button(0, 3, 1, None),
+                button(1, 1, c_cost, None),
+            ]
+        };
+        // one of each beats four diagonal presses, unless those are free
+        assert_eq!(solve(&game(buttons(1), 4, 4)), Ok(vec![1, 1, 1]));
+        assert_eq!(solve(&game(buttons(0), 4, 4)), Ok(vec![0, 0, 4]));
+        // x needs 2 diagonal presses mod 3 and y needs 1
+        assert_eq!(
+            solve(&game(buttons(1), 5, 4)),
+            Err(Infeasibility::NoSolution)
+        );
+        // a button that always overshoots is never pressed
+        let mut g = game(buttons(1), 4, 4);
+        g.buttons.push(button(5, 0, 0, None));
+        g.buttons.push(button(0, 0, 0, None));
+        assert_eq!(solve(&g), Ok(vec![1, 1, 1, 0, 0]));
+    }
+
+    #[test]
+    fn test_report() {
+        let games = parse_games(&example());
+        assert_eq!(day13_both(Some(example())), (480, 875318608908));
+        let reports = report(&games, 0, Some(100));
+        assert_eq!(
+            reports.iter().map(|r| r.winnable()).collect::<Vec<_>>(),
+            vec![true, false, true, false]
+        );
+        assert_eq!(reports[0].presses, vec![80, 40]);
+        assert_eq!(reports[0].cost, 280);
+        assert_eq!(reports[2].presses, vec![38, 86]);
+        assert_eq!(reports[1].reason, Some(Infeasibility::NoSolution));
+        assert_eq!(reports[1].presses, vec![0, 0]);
+
+        let reports = report(&games, PART2_OFFSET, None);
+        assert_eq!(
+            reports.iter().map(|r| r.winnable()).collect::<Vec<_>>(),
+            vec![false, true, false, true]
+        );
+        // the same machines with the part 2 prizes, but still limited to 100 presses
+        assert_eq!(total_cost(&games, PART2_OFFSET, Some(100)), 0);
+        assert_eq!(total_cost(&[], 0, None), 0);
+    }
+
+    #[test]
+    fn test_three_buttons_at_part2_scale() {
+        let t = 10000000000001;
+        let buttons = |c_cost, c_limit| {
+            vec![
+                button(3, 0, 1, None),
+                button(0, 3, 1, None),
+                button(1, 1, c_cost, c_limit),
+            ]
+        };
+        // t = 2 (mod 3), so two diagonal presses and the rest straight
+        let g = game(buttons(1, None), t, t);
+        assert_eq!(solve(&g), Ok(vec![(t - 2) / 3, (t - 2) / 3, 2]));
+        // free diagonal presses do all the work
+        assert_eq!(solve(&game(buttons(0, None), t, t + 3)), Ok(vec![0, 1, t]));
+        // but not when they are limited
+        assert_eq!(
+            solve(&game(buttons(0, Some(t - 3)), t, t)),
+            Ok(vec![1, 1, t - 3])
+        );
+        assert_eq!(
+            solve(&game(buttons(1, None), t, t + 1)),
+            Err(Infeasibility::NoSolution)
+        );
+
+        // the puzzle's first machine with the part 2 prize and a third button
+        let mut g = parse_games(&example()).remove(0).with_offset(PART2_OFFSET);
+        g.buttons.push(button(1, 1, 2, None));
+        let presses = solve(&g).unwrap();
+        let reached = g
+            .buttons
+            .iter()
+            .zip(presses.iter())
+            .fold((0, 0), |(x, y), (b, &n)| (x + b.x * n, y + b.y * n));
+        assert_eq!(reached, (g.prize.x, g.prize.y));
+
+        // a fourth button has to be enumerated, which is refused at this scale unless limited
+        let mut g = game(buttons(1, None), t, t);
+        g.buttons.push(button(2, 1, 1, None));
+        assert_eq!(solve(&g), Err(Infeasibility::SearchTooLarge));
+        g.buttons[3].limit = Some(10);
+        let presses = solve(&g).unwrap();
+        assert_eq!(g.cost(&presses), 2 + 2 * ((t - 2) / 3));
+    }
+
+    #[test]
+    fn test_matches_brute_force() {
+        let mut rng = test_rng(13);
+        let mut next = |n: u64| rng(n) as usize;
+        for round in 0..600 {
+            let count = 2 + round % 2;
+            let buttons = (0..count)
+                .map(|_| {
+                    let limit = (next(3) == 0).then(|| next(8));
+                    button(next(5), next(5), next(4), limit)
+                })
+                .collect::<Vec<_>>();
+            let g = game(buttons, next(20), next(20));
+            // every press vector with each button pressed at most 20 times
+            let mut best: Option<usize> = None;
+            let mut presses = vec![0; count];
+            loop {
+                let reached = g
+                    .buttons
+                    .iter()
+                    .zip(presses.iter())
+                    .fold((0, 0), |(x, y), (b, &n)| (x + b.x * n, y + b.y * n));
+                let allowed = g
+                    .buttons
+                    .iter()
+                    .zip(presses.iter())
+                    .all(|(b, &n)| b.limit.is_none_or(|limit| n <= limit));
+                if allowed && reached == (g.prize.x, g.prize.y) {
+                    let cost = g.cost(&presses);
+                    best = Some(best.map_or(cost, |best| best.min(cost)));
+                }
+                let Some(pos) = (0..count).find(|&pos| presses[pos] < 20) else {
+                    break;
+                };
+                presses[pos] += 1;
+                presses[..pos].iter_mut().for_each(|n| *n = 0);
+            }
+            let solved = solve(&g).ok().map(|presses| g.cost(&presses));
+            assert_eq!(solved, best, "{:?}", g);
+        }
+    }
+}
@@ -1,129 +1,267 @@
-use std::collections::HashSet;
-
 use crate::util::aoc_read_day_lines;
 
 const WIDTH: usize = 101;
 const HEIGHT: usize = 103;
 
-pub fn day14_part1(test_input: Option<(Vec<String>, usize, usize)>) -> usize {
-    let (lines, width, height) =
-        test_input.unwrap_or_else(|| (aoc_read_day_lines(14), WIDTH, HEIGHT));
-    let mut lines = lines
-        .iter()
-        .map(|line| {
-            let (p, v) = line[2..].split_once(" v=").unwrap();
-            let (px, py) = p.split_once(",").unwrap();
-            let (vx, vy) = v.split_once(",").unwrap();
-            (
-                (px.parse::<isize>().unwrap(), py.parse::<isize>().unwrap()),
-                (vx.parse::<isize>().unwrap(), vy.parse::<isize>().unwrap()),
-            )
-        })
-        .collect::<Vec<_>>();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Robot {
+    pub position: (isize, isize),
+    pub velocity: (isize, isize),
+}
 
-    const ITERS: usize = 100;
-    for (p, v) in lines.iter_mut() {
-        p.0 = (p.0 + v.0 * ITERS as isize).rem_euclid(width as isize);
-        p.1 = (p.1 + v.1 * ITERS as isize).rem_euclid(height as isize);
+impl Robot {
+    // p=x,y v=dx,dy
+    fn from_line(line: &str) -> Self {
+        let (p, v) = line[2..].split_once(" v=").unwrap();
+        let (px, py) = p.split_once(",").unwrap();
+        let (vx, vy) = v.split_once(",").unwrap();
+        Robot {
+            position: (px.parse().unwrap(), py.parse().unwrap()),
+            velocity: (vx.parse().unwrap(), vy.parse().unwrap()),
+        }
     }
+}
 
-    let mut in_quadrant_top_left = 0;
-    let mut in_quadrant_top_right = 0;
-    let mut in_quadrant_bottom_left = 0;
-    let mut in_quadrant_bottom_right = 0;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameStats {
+    pub mean: (f64, f64),
+    pub variance: (f64, f64),
+    // fraction of robots with another robot on the same or an orthogonally adjacent tile
+    pub density: f64,
+}
 
-    let mid_x: isize = width as isize / 2;
-    let mid_y: isize = height as isize / 2;
+// (g, s, t) with a*s + b*t = g = gcd(a, b)
+fn extended_gcd(a: i64, b: i64) -> (i64, i64, i64) {
+    if b == 0 {
+        (a, 1, 0)
+    } else {
+        let (g, s, t) = extended_gcd(b, a % b);
+        (g, t, s - (a / b) * t)
+    }
+}
 
-    for (p, _) in lines.iter() {
-        if p.0 == mid_x || p.1 == mid_y {
-            continue;
-        }
-        if p.0 < mid_x && p.1 < mid_y {
-            in_quadrant_top_left += 1;
-        } else if p.0 > mid_x && p.1 < mid_y {
-            in_quadrant_top_right += 1;
-        } else if p.0 < mid_x && p.1 > mid_y {
-            in_quadrant_bottom_left += 1;
-        } else {
-            in_quadrant_bottom_right += 1;
-        }
+// The smallest t >= 0 with t = a (mod m) and t = b (mod n), if there is one. The moduli do not
+// have to be coprime.
+fn crt(a: i64, m: i64, b: i64, n: i64) -> Option<i64> {
+    let (g, s, _) = extended_gcd(m, n);
+    if (b - a) % g != 0 {
+        return None;
     }
+    let lcm = m / g * n;
+    // a + m*k where m*k = b - a (mod n)
+    let k = ((b - a) / g % (n / g) * s % (n / g)) as i128;
+    Some(((a as i128 + m as i128 * k).rem_euclid(lcm as i128)) as i64)
+}
 
-    in_quadrant_top_left
-        * in_quadrant_top_right
-        * in_quadrant_bottom_left
-        * in_quadrant_bottom_right
+// A frame where the robots bunch up on both axes at once.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TreeFrame {
+    pub time: i64,
+    pub variance: (f64, f64),
 }
 
-pub fn day14_part2(test_input: Option<(Vec<String>, usize, usize)>) -> usize {
-    let (lines, width, height) =
-        test_input.unwrap_or_else(|| (aoc_read_day_lines(14), WIDTH, HEIGHT));
-    let mut lines = lines
-        .iter()
-        .map(|line| {
-            let (p, v) = line[2..].split_once(" v=").unwrap();
-            let (px, py) = p.split_once(",").unwrap();
-            let (vx, vy) = v.split_once(",").unwrap();
-            (
-                (px.parse::<isize>().unwrap(), py.parse::<isize>().unwrap()),
-                (vx.parse::<isize>().unwrap(), vy.parse::<isize>().unwrap()),
-            )
-        })
-        .collect::<Vec<_>>();
-
-    let mut points_set = lines.iter().map(|(p, _)| p).collect::<HashSet<_>>();
-    let mut iter = 0;
-    'outer: loop {
-        for (p, v) in lines.iter_mut() {
-            p.0 = (p.0 + v.0).rem_euclid(width as isize);
-            p.1 = (p.1 + v.1).rem_euclid(height as isize);
+// The robots as they were at time 0, plus a clock. Every robot moves in a straight line and wraps
+// around the edges, so its position at any time (negative too) is a single multiply and modulo,
+// and nothing is ever stepped tile by tile.
+pub struct Swarm {
+    pub robots: Vec<Robot>,
+    pub width: usize,
+    pub height: usize,
+    time: i64,
+}
+
+impl Swarm {
+    pub fn from_lines(lines: &[String], width: usize, height: usize) -> Self {
+        Swarm {
+            robots: lines
+                .iter()
+                .filter(|line| !line.is_empty())
+                .map(|line| Robot::from_line(line))
+                .collect(),
+            width,
+            height,
+            time: 0,
         }
-        points_set = lines.iter().map(|(p, _)| p).collect::<HashSet<_>>();
-        iter += 1;
-        // heuristic: christmas tree will have a long stretch of coordinates on a single x row
-        // vertically
-        for x in 0..WIDTH {
-            let mut longest_span = 0;
-            let mut count = 0;
-            for y in 0..HEIGHT {
-                if points_set.contains(&(x as isize, y as isize)) {
-                    count += 1;
-                } else {
-                    if count > longest_span {
-                        longest_span = count;
-                    }
-                    count = 0;
-                }
-            }
-            if longest_span > 10 {
-                println!("x: {}, count: {}", x, longest_span);
-                // cls
-                println!("\x1b[2J\x1b[1;1H");
-                // print grid
-                for y in 0..height {
-                    let line_str = (0..width)
-                        .map(|x| {
-                            if points_set.contains(&(x as isize, y as isize)) {
-                                '#'
-                            } else {
-                                '.'
-                            }
-                        })
-                        .collect::<String>();
-                    println!("{}", line_str);
-                }
-                println!("iter: {}", iter);
-                break 'outer;
+    }
+
+    pub fn time(&self) -> i64 {
+        self.time
+    }
+
+    pub fn jump_to(&mut self, time: i64) {
+        self.time = time;
+    }
+
+    pub fn step(&mut self) {
+        self.time += 1;
+    }
+
+    pub fn step_back(&mut self) {
+        self.time -= 1;
+    }
+
+    // Robot positions after `time` seconds, in input order.
+    pub fn positions_at(&self, time: i64) -> Vec<(usize, usize)> {
+        let (w, h) = (self.width as i64, self.height as i64);
+        self.robots
+            .iter()
+            .map(|r| {
+                let x = (r.position.0 as i64 + (r.velocity.0 as i64 * time).rem_euclid(w))
+                    .rem_euclid(w);
+                let y = (r.position.1 as i64 + (r.velocity.1 as i64 * time).rem_euclid(h))
+                    .rem_euclid(h);
+                (x as usize, y as usize)
+            })
+            .collect()
+    }
+
+    pub fn positions(&self) -> Vec<(usize, usize)> {
+        self.positions_at(self.time)
+    }
+
+    // Robots per quadrant in the order top left, top right, bottom left, bottom right. Robots on
+    // the middle row or column are in none of them.
+    pub fn quadrants_at(&self, time: i64) -> [usize; 4] {
+        let (mid_x, mid_y) = (self.width / 2, self.height / 2);
+        let mut quadrants = [0; 4];
+        for (x, y) in self.positions_at(time) {
+            if x == mid_x || y == mid_y {
+                continue;
             }
+            quadrants[(x > mid_x) as usize + 2 * (y > mid_y) as usize] += 1;
+        }
+        quadrants
+    }
+
+    pub fn safety_factor_at(&self, time: i64) -> usize {
+        self.quadrants_at(time).iter().product()
+    }
+
+    // Robots per tile, row by row.
+    fn counts_at(&self, time: i64) -> Vec<usize> {
+        let mut counts = vec![0; self.width * self.height];
+        for (x, y) in self.positions_at(time) {
+            counts[y * self.width + x] += 1;
         }
+        counts
     }
-    iter
+
+    pub fn stats_at(&self, time: i64) -> FrameStats {
+        let positions = self.positions_at(time);
+        let n = positions.len().max(1) as f64;
+        let mean = |coord: fn(&(usize, usize)) -> usize| {
+            positions.iter().map(|p| coord(p) as f64).sum::<f64>() / n
+        };
+        let variance = |coord: fn(&(usize, usize)) -> usize, mean: f64| {
+            positions
+                .iter()
+                .map(|p| (coord(p) as f64 - mean).powi(2))
+                .sum::<f64>()
+                / n
+        };
+        let (mean_x, mean_y) = (mean(|p| p.0), mean(|p| p.1));
+
+        let counts = self.counts_at(time);
+        let crowded = positions
+            .iter()
+            .filter(|&&(x, y)| {
+                counts[y * self.width + x] > 1
+                    || [(0, 1), (1, 0), (0, -1), (-1, 0)].iter().any(|&(dx, dy)| {
+                        let (Some(nx), Some(ny)) =
+                            (x.checked_add_signed(dx), y.checked_add_signed(dy))
+                        else {
+                            return false;
+                        };
+                        nx < self.width && ny < self.height && counts[ny * self.width + nx] > 0
+                    })
+            })
+            .count();
+        FrameStats {
+            mean: (mean_x, mean_y),
+            variance: (variance(|p| p.0, mean_x), variance(|p| p.1, mean_y)),
+            density: crowded as f64 / n,
+        }
+    }
+
+    // Variance of one coordinate of every robot, for times 0..modulus.
+    fn axis_variances(&self, coord: impl Fn(&Robot) -> (isize, isize), modulus: usize) -> Vec<f64> {
+        let m = modulus as i64;
+        let n = self.robots.len().max(1) as f64;
+        (0..m)
+            .map(|t| {
+                let values = self
+                    .robots
+                    .iter()
+                    .map(|r| {
+                        let (p, v) = coord(r);
+                        (p as i64 + v as i64 * t).rem_euclid(m) as f64
+                    })
+                    .collect::<Vec<_>>();
+                let mean = values.iter().sum::<f64>() / n;
+                values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n
+            })
+            .collect()
+    }
+
+    // The frame where the robots are most bunched up. x positions repeat every `width` seconds
+    // and y positions every `height`, so the time with the least x variance is searched within
+    // one x period and likewise for y, and the two are combined with the Chinese remainder
+    // theorem. None if no time has both, which only happens for non-coprime dimensions.
+    pub fn find_tree(&self) -> Option<TreeFrame> {
+        let argmin = |values: &[f64]| {
+            (0..values.len())
+                .min_by(|&a, &b| values[a].total_cmp(&values[b]))
+                .unwrap() as i64
+        };
+        let x_variances = self.axis_variances(|r| (r.position.0, r.velocity.0), self.width);
+        let y_variances = self.axis_variances(|r| (r.position.1, r.velocity.1), self.height);
+        let (tx, ty) = (argmin(&x_variances), argmin(&y_variances));
+        let time = crt(tx, self.width as i64, ty, self.height as i64)?;
+        Some(TreeFrame {
+            time,
+            variance: (x_variances[tx as usize], y_variances[ty as usize]),
+        })
+    }
+
+    // The puzzle's picture: robot count per tile, '.' for none.
+    pub fn render_at(&self, time: i64) -> String {
+        self.counts_at(time)
+            .chunks(self.width)
+            .map(|row| {
+                row.iter()
+                    .map(|&count| match count {
+                        0 => '.',
+                        1..=9 => char::from(b'0' + count as u8),
+                        _ => '+',
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+fn read_swarm(test_input: Option<(Vec<String>, usize, usize)>) -> Swarm {
+    let (lines, width, height) =
+        test_input.unwrap_or_else(|| (aoc_read_day_lines(14), WIDTH, HEIGHT));
+    Swarm::from_lines(&lines, width, height)
+}
+
+pub fn day14_part1(test_input: Option<(Vec<String>, usize, usize)>) -> usize {
+    read_swarm(test_input).safety_factor_at(100)
+}
+
+pub fn day14_part2(test_input: Option<(Vec<String>, usize, usize)>) -> usize {
+    read_swarm(test_input)
+        .find_tree()
+        .expect("no frame where both axes converge")
+        .time as usize
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::util::test_rng;
 
     const EXAMPLE: &str = "p=0,4 v=3,-3
 p=6,3 v=-1,-3
@@ -155,4 +293,116 @@ p=9,5 v=-3,-3";
         assert_eq!((10 as isize).rem_euclid(4), 2);
         assert_eq!((-5 as isize).rem_euclid(4), 3);
     }
+
+    fn example() -> Swarm {
+        Swarm::from_lines(
+            &EXAMPLE.lines().map(|s| s.to_string()).collect::<Vec<_>>(),
+            11,
+            7,
+        )
+    }
+
+    #[test]
+    fn test_swarm_time() {
+        let mut swarm = Swarm::from_lines(&["p=2,4 v=2,-3".to_string()], 11, 7);
+        let expected = [(2, 4), (4, 1), (6, 5), (8, 2), (10, 6), (1, 3)];
+        for &position in expected.iter() {
+            assert_eq!(swarm.positions(), vec![position]);
+            swarm.step();
+        }
+        for &position in expected.iter().rev() {
+            swarm.step_back();
+            assert_eq!(swarm.positions(), vec![position]);
+        }
+        assert_eq!(swarm.time(), 0);
+        // back to the start after lcm(11, 7) seconds, in either direction
+        swarm.jump_to(77);
+        assert_eq!(swarm.positions(), vec![(2, 4)]);
+        assert_eq!(swarm.positions_at(-1), swarm.positions_at(76));
+        assert_eq!(
+            swarm.positions_at(1_000_000_000_000),
+            swarm.positions_at(1_000_000_000_000 % 77)
+        );
+    }
+
+    #[test]
+    fn test_quadrants_and_render() {
+        let swarm = example();
+        assert_eq!(swarm.quadrants_at(100), [1, 3, 4, 1]);
+        assert_eq!(swarm.safety_factor_at(100), 12);
+        assert_eq!(
+            swarm.render_at(0),
+            "1.12.......
+...........
+...........
+......11.11
+1.1........
+.........1.
+.......1..."
+        );
+        assert_eq!(
+            swarm.render_at(100),
+            "......2..1.
+...........
+1..........
+.11........
+.....1.....
+...12......
+.1....1...."
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let swarm = Swarm::from_lines(
+            &["p=1,1 v=0,0", "p=1,2 v=0,0", "p=3,3 v=0,0", "p=3,3 v=0,0"].map(|s| s.to_string()),
+            5,
+            5,
+        );
+        let stats = swarm.stats_at(0);
+        assert_eq!(stats.mean, (2.0, 2.25));
+        assert_eq!(stats.variance, (1.0, 0.6875));
+        assert_eq!(stats.density, 1.0);
+        let spread =
+            Swarm::from_lines(&["p=0,0 v=0,0", "p=4,4 v=0,0"].map(|s| s.to_string()), 5, 5);
+        assert_eq!(spread.stats_at(0).density, 0.0);
+    }
+
+    #[test]
+    fn test_crt() {
+        assert_eq!(crt(2, 101, 5, 103), Some(5052));
+        assert_eq!(5052 % 101, 2);
+        assert_eq!(5052 % 103, 5);
+        assert_eq!(crt(0, 101, 0, 103), Some(0));
+        assert_eq!(crt(3, 10, 1, 4), Some(13));
+        // 3 mod 10 is odd, 0 mod 4 is even
+        assert_eq!(crt(3, 10, 0, 4), None);
+    }
+
+    #[test]
+    fn test_find_tree() {
+        // robots that all meet in a small blob at time 6000, scattered the rest of the time
+        let (width, height, time) = (101, 103, 6000);
+        let mut rng = test_rng(14);
+        let mut next = |n: u64| rng(n) as i64;
+        let lines = (0..300)
+            .map(|_| {
+                let (bx, by) = (45 + next(10), 40 + next(15));
+                let (vx, vy) = (next(201) - 100, next(201) - 100);
+                let px = (bx - vx * time).rem_euclid(width);
+                let py = (by - vy * time).rem_euclid(height);
+                format!("p={},{} v={},{}", px, py, vx, vy)
+            })
+            .collect::<Vec<_>>();
+        let swarm = Swarm::from_lines(&lines, width as usize, height as usize);
+        let frame = swarm.find_tree().unwrap();
+        assert_eq!(frame.time, time);
+        let stats = swarm.stats_at(frame.time);
+        assert_eq!(stats.variance, frame.variance);
+        assert!(stats.variance.0 < swarm.stats_at(frame.time - 1).variance.0);
+        assert_eq!(
+            day14_part2(Some((lines, width as usize, height as usize))),
+            time as usize
+        );
+    }
 }
@@ -1,9 +1,10 @@
 use std::collections::VecDeque;
+use std::fmt;
 
 use crate::util::aoc_read_day_lines;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-enum Move {
+pub enum Move {
     Up,
     Down,
     Left,
@@ -11,16 +12,16 @@ enum Move {
 }
 
 impl Move {
-    fn from_char(c: char) -> Move {
+    pub fn from_char(c: char) -> Move {
         match c {
             '^' => Move::Up,
             'v' => Move::Down,
             '<' => Move::Left,
             '>' => Move::Right,
-            _ => unreachable!(),
+            _ => panic!("unexpected move {:?}", c),
         }
     }
-    fn to_direction(self) -> (isize, isize) {
+    pub fn to_direction(self) -> (isize, isize) {
         match self {
             Move::Up => (0, -1),
             Move::Down => (0, 1),
@@ -28,505 +29,451 @@ impl Move {
             Move::Right => (1, 0),
         }
     }
+    pub fn to_char(self) -> char {
+        match self {
+            Move::Up => '^',
+            Move::Down => 'v',
+            Move::Left => '<',
+            Move::Right => '>',
+        }
+    }
+    pub fn opposite(self) -> Move {
+        match self {
+            Move::Up => Move::Down,
+            Move::Down => Move::Up,
+            Move::Left => Move::Right,
+            Move::Right => Move::Left,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-enum Object {
+enum Cell {
     Empty,
     Wall,
-    Robot,
-    Rock,
-    BoxLeft,
-    BoxRight,
+    // part of the box with this id
+    Box(usize),
 }
 
-impl Object {
-    fn from_char(c: char) -> Object {
-        match c {
-            '.' => Object::Empty,
-            '#' => Object::Wall,
-            '@' => Object::Robot,
-            'O' => Object::Rock,
-            '[' => Object::BoxLeft,
-            ']' => Object::BoxRight,
-            _ => unreachable!(),
-        }
+// A box covering `width` cells to the right of (x, y), inclusive.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct WarehouseBox {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+}
+
+impl WarehouseBox {
+    pub fn gps(&self) -> usize {
+        100 * self.y + self.x
     }
-    fn to_char(self) -> char {
-        match self {
-            Object::Empty => '.',
-            Object::Wall => '#',
-            Object::Robot => '@',
-            Object::Rock => 'O',
-            Object::BoxLeft => '[',
-            Object::BoxRight => ']',
-        }
+}
+
+// What one robot move did. The robot stays put when the move is blocked.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Step {
+    pub direction: Move,
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+    // ids of the boxes pushed one cell along with the robot
+    pub pushed: Vec<usize>,
+}
+
+impl Step {
+    pub fn moved(&self) -> bool {
+        self.from != self.to
     }
 }
 
-pub fn day15_part1(test_input: Option<Vec<String>>) -> usize {
-    let lines = test_input.unwrap_or(aoc_read_day_lines(15));
-    let mut robot_pos = (0, 0);
-    // take until the first empty line
-    let mut map_lines = lines
-        .iter()
-        .take_while(|line| !line.is_empty())
-        .enumerate()
-        .map(|(y, line)| {
-            line.chars()
-                .enumerate()
-                .map(|(x, c)| {
-                    let obj = Object::from_char(c);
-                    if obj == Object::Robot {
-                        robot_pos = (x, y);
-                    }
-                    obj
-                })
-                .collect::<Vec<_>>()
-        })
-        .collect::<Vec<_>>();
-    // concat all movelines to single line
-    let moves = lines
-        .iter()
-        .skip(map_lines.len() + 1)
-        .flat_map(|line| line.chars())
-        .map(|c| Move::from_char(c))
-        .collect::<Vec<_>>();
-
-    let mut i = 0;
-    'mainloop: for m in moves.iter() {
-        i += 1;
-        // clear screen, print map, sleep 500 ms
-        // println!("\x1b[2J\x1b[H");
-        // println!("move {}, trying to go {:?}", i, m.to_direction());
-        // println!("robot pos: {:?}", robot_pos);
-        // for line in map_lines.iter() {
-        //     println!("{}", line.iter().map(|o| o.to_char()).collect::<String>());
-        // }
-        // std::thread::sleep(std::time::Duration::from_millis(10));
-        let direction = m.to_direction();
-        let cur_pos = robot_pos;
-        let new_pos_candidate = (
-            robot_pos.0 as isize + direction.0,
-            robot_pos.1 as isize + direction.1,
-        );
-        if cant_push(&map_lines, new_pos_candidate) {
-            continue;
-        }
-        // find the first empty space in the direction of the move. abort if any walls
-        let mut rocks_pushed: usize = 0;
-        loop {
-            let pos = (
-                new_pos_candidate.0 + rocks_pushed as isize * direction.0,
-                new_pos_candidate.1 + rocks_pushed as isize * direction.1,
-            );
-            if cant_push(&map_lines, pos) {
-                continue 'mainloop;
-            }
-            if pos_is(&map_lines, pos, Object::Empty) {
-                break;
-            }
-            rocks_pushed += 1;
+// < (4,4)->(3,4) pushed 2,5
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({},{})",
+            self.direction.to_char(),
+            self.from.0,
+            self.from.1
+        )?;
+        if !self.moved() {
+            return write!(f, " blocked");
         }
-        // starting from the furthest rock pushed, push all rocks in the direction of the move
-        // then move the robot and replace its old position with empty
-        for i in 1..=rocks_pushed {
-            let rock_new_y = new_pos_candidate.1 as isize + i as isize * direction.1;
-            let rock_new_x = new_pos_candidate.0 as isize + i as isize * direction.0;
-            map_lines[rock_new_y as usize][rock_new_x as usize] = Object::Rock;
+        write!(f, "->({},{})", self.to.0, self.to.1)?;
+        if !self.pushed.is_empty() {
+            let ids = self
+                .pushed
+                .iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>();
+            write!(f, " pushed {}", ids.join(","))?;
         }
-        robot_pos = (new_pos_candidate.0 as usize, new_pos_candidate.1 as usize);
-        map_lines[robot_pos.1 as usize][robot_pos.0 as usize] = Object::Robot;
-        map_lines[cur_pos.1 as usize][cur_pos.0 as usize] = Object::Empty;
+        Ok(())
     }
+}
 
-    print_map(&map_lines);
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Warehouse {
+    pub width: usize,
+    pub height: usize,
+    pub robot: (usize, usize),
+    pub boxes: Vec<WarehouseBox>,
+    cells: Vec<Cell>,
+}
 
-    let mut gps = 0;
-    for y in 0..map_lines.len() {
-        for x in 0..map_lines[y].len() {
-            if let Object::Rock = map_lines[y][x] {
-                gps += 100 * y + x
+impl Warehouse {
+    // Every map cell becomes `scale` cells wide: walls and floor are repeated, the robot keeps the
+    // leftmost cell and an 'O' becomes one box `scale` cells wide. Boxes can also be written out
+    // as '[', any number of '=', ']', which is how boxes wider than one cell are displayed.
+    pub fn from_lines(lines: &[String], scale: usize) -> Self {
+        assert!(scale > 0, "scale must be at least 1");
+        let lines = lines
+            .iter()
+            .take_while(|line| !line.is_empty())
+            .collect::<Vec<_>>();
+        let height = lines.len();
+        let width = lines.first().map_or(0, |line| line.len() * scale);
+        let mut warehouse = Warehouse {
+            width,
+            height,
+            robot: (0, 0),
+            boxes: Vec::new(),
+            cells: vec![Cell::Empty; width * height],
+        };
+        let mut robots = 0;
+        for (y, line) in lines.iter().enumerate() {
+            assert_eq!(
+                line.len() * scale,
+                width,
+                "map rows must all have the same width"
+            );
+            let mut open_box = None;
+            for (col, c) in line.chars().enumerate() {
+                let x = col * scale;
+                match c {
+                    '.' => {}
+                    '#' => warehouse.cells[y * width + x..y * width + x + scale].fill(Cell::Wall),
+                    '@' => {
+                        warehouse.robot = (x, y);
+                        robots += 1;
+                    }
+                    'O' => warehouse.add_box(x, y, scale),
+                    '[' => open_box = Some(x),
+                    '=' => assert!(open_box.is_some(), "'=' outside of a box in row {}", y),
+                    ']' => {
+                        let start = open_box.take().expect("']' without '['");
+                        warehouse.add_box(start, y, x + scale - start);
+                    }
+                    _ => panic!("unexpected map cell {:?}", c),
+                }
             }
+            assert!(open_box.is_none(), "unclosed box in row {}", y);
         }
+        assert_eq!(robots, 1, "the map must have exactly one robot");
+        warehouse
     }
-    gps
-}
 
-// prints map when assertion fails
-macro_rules! assert_print_map {
-    ($cond:expr, $map:expr, $msg:expr) => {
-        if std::env::var("AOC_DEBUG").is_ok() {
-            if !$cond {
-                print_map($map);
-                panic!("assertion failed: {}", $msg);
-            }
-        }
-    };
-}
+    fn add_box(&mut self, x: usize, y: usize, width: usize) {
+        let id = self.boxes.len();
+        self.boxes.push(WarehouseBox { x, y, width });
+        self.fill_box(id, Cell::Box(id));
+    }
 
-pub fn day15_part2(test_input: Option<Vec<String>>) -> usize {
-    let lines = test_input.unwrap_or(aoc_read_day_lines(15));
-    let mut robot_pos = (0, 0);
-    // take until the first empty line
-    let mut map_lines = vec![];
-    for (y, line) in lines.iter().take_while(|line| !line.is_empty()).enumerate() {
-        let chars = line.chars().enumerate().collect::<Vec<_>>();
-        let mut i = 0;
-        let mut x = 0;
-        let mut line_vec = vec![];
-        while i < chars.len() {
-            let obj = Object::from_char(chars[i].1);
-            if obj == Object::Robot {
-                robot_pos = (x, y);
-                line_vec.push(Object::Robot);
-                line_vec.push(Object::Empty);
-            }
-            if obj == Object::Wall {
-                line_vec.push(obj);
-                line_vec.push(obj);
-            }
-            if obj == Object::Rock {
-                line_vec.push(Object::BoxLeft);
-                line_vec.push(Object::BoxRight);
-            }
-            if obj == Object::Empty {
-                line_vec.push(Object::Empty);
-                line_vec.push(Object::Empty);
-            }
-            i += 1;
-            x += 2;
+    fn fill_box(&mut self, id: usize, cell: Cell) {
+        let b = self.boxes[id];
+        let start = b.y * self.width + b.x;
+        self.cells[start..start + b.width].fill(cell);
+    }
+
+    // The cell one step from (x, y), or None past the edge of the map.
+    fn neighbour(&self, (x, y): (usize, usize), direction: Move) -> Option<(usize, usize)> {
+        let (dx, dy) = direction.to_direction();
+        let nx = x.checked_add_signed(dx).filter(|&nx| nx < self.width)?;
+        let ny = y.checked_add_signed(dy).filter(|&ny| ny < self.height)?;
+        Some((nx, ny))
+    }
+
+    // The cells a box moves into when pushed.
+    fn cells_ahead(&self, id: usize, direction: Move) -> Vec<Option<(usize, usize)>> {
+        let b = self.boxes[id];
+        match direction {
+            Move::Left => vec![self.neighbour((b.x, b.y), direction)],
+            Move::Right => vec![self.neighbour((b.x + b.width - 1, b.y), direction)],
+            Move::Up | Move::Down => (b.x..b.x + b.width)
+                .map(|x| self.neighbour((x, b.y), direction))
+                .collect(),
         }
-        map_lines.push(line_vec);
     }
-    // concat all movelines to single line
-    let moves = lines
-        .iter()
-        .skip(map_lines.len() + 1)
-        .flat_map(|line| line.chars())
-        .map(|c| Move::from_char(c))
-        .collect::<Vec<_>>();
-
-    let mut eval_queue: VecDeque<(isize, isize)> = VecDeque::from([]);
-    let mut to_move_stack: Vec<(isize, isize)> = vec![];
-
-    for m in moves.iter() {
-        eval_queue.clear();
-        to_move_stack.clear();
-        assert_print_map!(
-            every_boxleft_has_boxright_next_to_it(&map_lines),
-            &map_lines,
-            "every boxleft has boxright next to it"
-        );
-        let direction = m.to_direction();
-
-        let cur_pos = robot_pos;
-        eval_queue.push_back((cur_pos.0 as isize, cur_pos.1 as isize));
-        assert!(map_lines[eval_queue[0].1 as usize][eval_queue[0].0 as usize] == Object::Robot);
-        while let Some(cur) = eval_queue.pop_front() {
-            if to_move_stack.contains(&cur) {
-                continue;
-            }
-            if *m == Move::Left {
-                let cur_obj = map_lines[cur.1 as usize][cur.0 as usize];
-                match cur_obj {
-                    Object::BoxLeft => {
-                        let on_left = (cur.0 - 1, cur.1);
-                        if cant_push(&map_lines, on_left) {
-                            eval_queue.clear();
-                            to_move_stack.clear();
-                            break;
-                        }
-                        to_move_stack.push(cur);
-                        if pos_is(&map_lines, on_left, Object::Empty) {
-                            continue;
-                        }
-                        if pos_is(&map_lines, on_left, Object::BoxRight) {
-                            assert!(pos_is(
-                                &map_lines,
-                                (on_left.0 + 1, on_left.1),
-                                Object::BoxLeft
-                            ));
-                            eval_queue.push_back((on_left.0 - 1, on_left.1));
-                        }
-                    }
-                    Object::Robot => {
-                        let on_left = (cur.0 - 1, cur.1);
-                        if cant_push(&map_lines, on_left) {
-                            eval_queue.clear();
-                            to_move_stack.clear();
-                            break;
-                        }
-                        to_move_stack.push(cur);
-                        if pos_is(&map_lines, on_left, Object::Empty) {
-                            continue;
-                        }
-                        if pos_is(&map_lines, on_left, Object::BoxRight) {
-                            assert!(pos_is(
-                                &map_lines,
-                                (on_left.0 - 1, on_left.1),
-                                Object::BoxLeft
-                            ));
-                            eval_queue.push_back((on_left.0 - 1, on_left.1));
-                        }
-                    }
-                    _ => unreachable!(),
-                }
-            } else if *m == Move::Right {
-                let cur_obj = map_lines[cur.1 as usize][cur.0 as usize];
-                match cur_obj {
-                    Object::BoxLeft => {
-                        let on_right = (cur.0 + 2, cur.1);
-                        if cant_push(&map_lines, on_right) {
-                            to_move_stack.clear();
-                            eval_queue.clear();
-                            break;
-                        }
-                        to_move_stack.push(cur);
-                        if pos_is(&map_lines, on_right, Object::Empty) {
-                            continue;
-                        }
-                        if pos_is(&map_lines, on_right, Object::BoxLeft) {
-                            assert!(pos_is(
-                                &map_lines,
-                                (on_right.0 + 1, on_right.1),
-                                Object::BoxRight
-                            ));
-                            eval_queue.push_back(on_right);
-                        }
-                    }
-                    Object::Robot => {
-                        let on_right = (cur.0 + 1, cur.1);
-                        if cant_push(&map_lines, on_right) {
-                            eval_queue.clear();
-                            to_move_stack.clear();
-                            break;
-                        }
-                        to_move_stack.push(cur);
-                        if pos_is(&map_lines, on_right, Object::Empty) {
-                            continue;
-                        }
-                        if pos_is(&map_lines, on_right, Object::BoxLeft) {
-                            assert!(pos_is(
-                                &map_lines,
-                                (on_right.0 + 1, on_right.1),
-                                Object::BoxRight
-                            ));
-                            eval_queue.push_back(on_right);
-                        }
-                    }
-                    _ => unreachable!(),
-                }
-            } else if *m == Move::Up {
-                let cur_obj = map_lines[cur.1 as usize][cur.0 as usize];
-                match cur_obj {
-                    Object::BoxLeft => {
-                        let above_direct = (cur.0, cur.1 - 1);
-                        let above_upright = (cur.0 + 1, cur.1 - 1);
-                        if cant_push(&map_lines, above_direct) {
-                            to_move_stack.clear();
-                            eval_queue.clear();
-                            break;
-                        }
-                        if cant_push(&map_lines, above_upright) {
-                            to_move_stack.clear();
-                            eval_queue.clear();
-                            break;
-                        }
-                        to_move_stack.push(cur);
-                        if pos_is(&map_lines, above_direct, Object::Empty)
-                            && pos_is(&map_lines, above_upright, Object::Empty)
-                        {
-                            continue;
-                        }
-                        if pos_is(&map_lines, above_direct, Object::BoxLeft) {
-                            eval_queue.push_back(above_direct);
-                        } else if pos_is(&map_lines, above_direct, Object::BoxRight) {
-                            assert!(pos_is(
-                                &map_lines,
-                                (above_direct.0 - 1, above_direct.1),
-                                Object::BoxLeft
-                            ));
-                            eval_queue.push_back((above_direct.0 - 1, above_direct.1));
-                        }
 
-                        if pos_is(&map_lines, above_upright, Object::BoxLeft) {
-                            eval_queue.push_back(above_upright);
-                        }
-                    }
-                    Object::Robot => {
-                        let above = (cur.0, cur.1 - 1);
-                        if cant_push(&map_lines, above) {
-                            to_move_stack.clear();
-                            eval_queue.clear();
-                            break;
-                        }
-                        to_move_stack.push(cur);
-                        if pos_is(&map_lines, above, Object::BoxLeft) {
-                            eval_queue.push_back(above);
-                        } else if pos_is(&map_lines, above, Object::BoxRight) {
-                            assert!(pos_is(&map_lines, (above.0 - 1, above.1), Object::BoxLeft));
-                            eval_queue.push_back((above.0 - 1, above.1));
-                        } else if pos_is(&map_lines, above, Object::Empty) {
-                            continue;
+    // Every box that has to move for something to enter `targets`, in the order they were
+    // found, or None if a wall or the edge of the map is in the way.
+    fn push_set(
+        &self,
+        targets: Vec<Option<(usize, usize)>>,
+        direction: Move,
+    ) -> Option<Vec<usize>> {
+        let mut pushed = Vec::new();
+        let mut seen = vec![false; self.boxes.len()];
+        let mut queue = VecDeque::from([targets]);
+        while let Some(targets) = queue.pop_front() {
+            for target in targets {
+                let (x, y) = target?;
+                match self.cells[y * self.width + x] {
+                    Cell::Empty => {}
+                    Cell::Wall => return None,
+                    Cell::Box(id) => {
+                        if !seen[id] {
+                            seen[id] = true;
+                            pushed.push(id);
+                            queue.push_back(self.cells_ahead(id, direction));
                         }
                     }
-                    _ => unreachable!(),
-                }
-            } else if *m == Move::Down {
-                let cur_obj = map_lines[cur.1 as usize][cur.0 as usize];
-                match cur_obj {
-                    Object::BoxLeft => {
-                        let below_direct = (cur.0, cur.1 + 1);
-                        let below_downright = (cur.0 + 1, cur.1 + 1);
-                        if cant_push(&map_lines, below_direct) {
-                            to_move_stack.clear();
-                            eval_queue.clear();
-                            break;
-                        }
-                        if cant_push(&map_lines, below_downright) {
-                            to_move_stack.clear();
-                            eval_queue.clear();
-                            break;
-                        }
-                        to_move_stack.push(cur);
-                        if pos_is(&map_lines, below_direct, Object::Empty) && pos_is(&map_lines, below_downright, Object::Empty) {
-                            continue;
-                        }
-                        if pos_is(&map_lines, below_direct, Object::BoxLeft) {
-                            eval_queue.push_back(below_direct);
-                        } else if pos_is(&map_lines, below_direct, Object::BoxRight) {
-                            assert!(pos_is(&map_lines, (below_direct.0 - 1, below_direct.1), Object::BoxLeft));
-                            eval_queue.push_back((below_direct.0 - 1, below_direct.1));
-                        }
-                        if pos_is(&map_lines, below_downright, Object::BoxLeft) {
-                            eval_queue.push_back(below_downright);
-                        }
-                    }
-                    Object::Robot => {
-                        let below = (cur.0, cur.1 + 1);
-                        if cant_push(&map_lines, below) {
-                            to_move_stack.clear();
-                            eval_queue.clear();
-                            break;
-                        }
-                        to_move_stack.push(cur);
-                        if pos_is(&map_lines, below, Object::BoxLeft) {
-                            eval_queue.push_back(below);
-                        } else if pos_is(&map_lines, below, Object::BoxRight) {
-                            assert!(pos_is(&map_lines, (below.0 - 1, below.1), Object::BoxLeft));
-                            eval_queue.push_back((below.0 - 1, below.1));
-                        } else if pos_is(&map_lines, below, Object::Empty) {
-                            continue;
-                        }
-                    }
-                    other => assert_print_map!(false, &map_lines, format!("invalid object in push_stack: {:?}, push_stack: {:?}, push_stack_types: {:?}", other, to_move_stack, to_move_stack.iter().map(|(x, y)| map_lines[*y as usize][*x as usize]).collect::<Vec<_>>())),
                 }
             }
         }
+        Some(pushed)
+    }
 
-        while let Some(pos) = to_move_stack.pop() {
-            let obj = map_lines[pos.1 as usize][pos.0 as usize];
-            match obj {
-                Object::BoxLeft => {
-                    if *m == Move::Left {
-                        let empty_pos = (pos.0 - 1, pos.1);
-                        assert!(map_lines[empty_pos.1 as usize][empty_pos.0 as usize] == Object::Empty, "target at {:?} is not empty {:?}", empty_pos, map_lines[empty_pos.1 as usize][empty_pos.0 as usize]);
-                        map_lines[empty_pos.1 as usize][empty_pos.0 as usize] = Object::BoxLeft;
-                        map_lines[pos.1 as usize][pos.0 as usize] = Object::BoxRight;
-                        map_lines[pos.1 as usize][(pos.0 + 1) as usize] = Object::Empty;
-                    } else if *m == Move::Right {
-                        let empty_pos = (pos.0 + 2, pos.1);
-                        assert!(map_lines[empty_pos.1 as usize][empty_pos.0 as usize] == Object::Empty, "target at {:?} is not empty {:?}", empty_pos, map_lines[empty_pos.1 as usize][empty_pos.0 as usize]);
-                        map_lines[pos.1 as usize][(pos.0 + 1) as usize] = Object::BoxLeft;
-                        map_lines[pos.1 as usize][(pos.0 + 2) as usize] = Object::BoxRight;
-                        map_lines[pos.1 as usize][pos.0 as usize] = Object::Empty;
-                    } else if *m == Move::Up {
-                        let empty_pos = (pos.0, pos.1 - 1);
-                        assert!(map_lines[empty_pos.1 as usize][empty_pos.0 as usize] == Object::Empty, "target at {:?} is not empty {:?}", empty_pos, map_lines[empty_pos.1 as usize][empty_pos.0 as usize]);
-                        assert!(map_lines[empty_pos.1 as usize][(empty_pos.0 + 1) as usize] == Object::Empty, "target at {:?} is not empty {:?}", (empty_pos.0 + 1, empty_pos.1), map_lines[empty_pos.1 as usize][(empty_pos.0 + 1) as usize]);
-                        map_lines[(pos.1 - 1) as usize][pos.0 as usize] = Object::BoxLeft;
-                        map_lines[(pos.1 - 1) as usize][(pos.0 + 1) as usize] = Object::BoxRight;
-                        map_lines[pos.1 as usize][pos.0 as usize] = Object::Empty;
-                        map_lines[pos.1 as usize][(pos.0 + 1) as usize] = Object::Empty;
-                    } else if *m == Move::Down {
-                        let empty_pos = (pos.0, pos.1 + 1);
-                        assert!(map_lines[empty_pos.1 as usize][empty_pos.0 as usize] == Object::Empty, "target at {:?} is not empty {:?}", empty_pos, map_lines[empty_pos.1 as usize][empty_pos.0 as usize]);
-                        assert!(map_lines[(empty_pos.1) as usize][(empty_pos.0 + 1) as usize] == Object::Empty, "target at {:?} is not empty {:?}", (empty_pos.0, empty_pos.1 + 1), map_lines[(empty_pos.1 + 1) as usize][(empty_pos.0 + 1) as usize]);
-                        map_lines[(pos.1 + 1) as usize][pos.0 as usize] = Object::BoxLeft;
-                        map_lines[(pos.1 + 1) as usize][(pos.0 + 1) as usize] = Object::BoxRight;
-                        map_lines[pos.1 as usize][pos.0 as usize] = Object::Empty;
-                        map_lines[pos.1 as usize][(pos.0 + 1) as usize] = Object::Empty;
-                    }
+    // Shift the given boxes and the robot one cell in `direction`. Boxes are lifted off the grid
+    // before any is put down so that the order does not matter.
+    fn shift(&mut self, ids: &[usize], robot: (usize, usize), direction: Move) {
+        let (dx, dy) = direction.to_direction();
+        for &id in ids {
+            self.fill_box(id, Cell::Empty);
+        }
+        for &id in ids {
+            let b = &mut self.boxes[id];
+            b.x = b.x.wrapping_add_signed(dx);
+            b.y = b.y.wrapping_add_signed(dy);
+            self.fill_box(id, Cell::Box(id));
+        }
+        self.robot = robot;
+    }
+
+    pub fn step(&mut self, direction: Move) -> Step {
+        let from = self.robot;
+        let mut step = Step {
+            direction,
+            from,
+            to: from,
+            pushed: Vec::new(),
+        };
+        let target = self.neighbour(from, direction);
+        if let Some(pushed) = self.push_set(vec![target], direction) {
+            let to = target.unwrap();
+            self.shift(&pushed, to, direction);
+            step.to = to;
+            step.pushed = pushed;
+        }
+        step
+    }
+
+    // Reverse a step that was the last one applied.
+    pub fn undo(&mut self, step: &Step) {
+        self.shift(&step.pushed, step.from, step.direction.opposite());
+    }
+
+    // The grid and the box list agree: every box covers exactly its own cells, inside the map and
+    // off the walls, and the robot stands on a free cell.
+    pub fn is_consistent(&self) -> bool {
+        let mut expected = self
+            .cells
+            .iter()
+            .map(|&cell| {
+                if cell == Cell::Wall {
+                    cell
+                } else {
+                    Cell::Empty
                 }
-                Object::Robot => {
-                    map_lines[pos.1 as usize][pos.0 as usize] = Object::Empty;
-                    let new_pos = (pos.0 as isize + direction.0, pos.1 as isize + direction.1);
-                    if cant_push(&map_lines, new_pos) {
-                        assert_print_map!(false, &map_lines, "robot moved out of bounds");
-                    }
-                    map_lines[new_pos.1 as usize][new_pos.0 as usize] = Object::Robot;
-                    robot_pos = (new_pos.0 as usize, new_pos.1 as usize);
+            })
+            .collect::<Vec<_>>();
+        for (id, b) in self.boxes.iter().enumerate() {
+            if b.y >= self.height || b.width == 0 || b.x + b.width > self.width {
+                return false;
+            }
+            for cell in &mut expected[b.y * self.width + b.x..b.y * self.width + b.x + b.width] {
+                if *cell != Cell::Empty {
+                    return false;
                 }
-                other => assert_print_map!(false, &map_lines, format!("invalid object in push_stack: {:?}, push_stack: {:?}, push_stack_types: {:?}", other, to_move_stack, to_move_stack.iter().map(|(x, y)| map_lines[*y as usize][*x as usize]).collect::<Vec<_>>())),
+                *cell = Cell::Box(id);
             }
         }
+        let (x, y) = self.robot;
+        expected == self.cells
+            && x < self.width
+            && y < self.height
+            && self.cells[y * self.width + x] == Cell::Empty
     }
 
-    print_map(&map_lines);
+    pub fn gps_sum(&self) -> usize {
+        self.boxes.iter().map(|b| b.gps()).sum()
+    }
+}
 
-    let mut gps = 0;
-    for y in 0..map_lines.len() {
-        for x in 0..map_lines[y].len() {
-            if let Object::BoxLeft = map_lines[y][x] {
-                gps += 100 * y + x
+impl fmt::Display for Warehouse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for y in 0..self.height {
+            let mut line = String::with_capacity(self.width);
+            for x in 0..self.width {
+                line.push(match self.cells[y * self.width + x] {
+                    _ if (x, y) == self.robot => '@',
+                    Cell::Empty => '.',
+                    Cell::Wall => '#',
+                    Cell::Box(id) => {
+                        let b = self.boxes[id];
+                        if b.width == 1 {
+                            'O'
+                        } else if x == b.x {
+                            '['
+                        } else if x == b.x + b.width - 1 {
+                            ']'
+                        } else {
+                            '='
+                        }
+                    }
+                });
             }
+            writeln!(f, "{}", line)?;
         }
+        Ok(())
     }
-    gps
 }
 
-fn cant_push(map_lines: &Vec<Vec<Object>>, pos: (isize, isize)) -> bool {
-    !in_bounds(pos, map_lines) || map_lines[pos.1 as usize][pos.0 as usize] == Object::Wall
+// The warehouse after some number of moves, to resume a Replay from.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Snapshot {
+    pub position: usize,
+    pub warehouse: Warehouse,
 }
 
-fn pos_is(map_lines: &Vec<Vec<Object>>, pos: (isize, isize), obj: Object) -> bool {
-    map_lines[pos.1 as usize][pos.0 as usize] == obj
+// A warehouse run through a list of moves that can be stepped both ways. Every applied move is
+// logged as its Step, which has everything needed to reverse it, so going back never replays from
+// the start. A replay resumed from a snapshot cannot go back past the snapshot.
+pub struct Replay {
+    warehouse: Warehouse,
+    moves: Vec<Move>,
+    // position of the first logged step
+    start: usize,
+    log: Vec<Step>,
 }
 
-fn every_boxleft_has_boxright_next_to_it(map_lines: &Vec<Vec<Object>>) -> bool {
-    for y in 0..map_lines.len() {
-        for x in 0..map_lines[y].len() {
-            if map_lines[y][x] == Object::BoxLeft {
-                if !in_bounds((x as isize + 1, y as isize), map_lines)
-                    || map_lines[y][x + 1] != Object::BoxRight
-                {
-                    return false;
-                }
-            }
-            if map_lines[y][x] == Object::BoxRight {
-                if !in_bounds((x as isize - 1, y as isize), map_lines)
-                    || map_lines[y][x - 1] != Object::BoxLeft
-                {
-                    return false;
-                }
+impl Replay {
+    pub fn new(warehouse: Warehouse, moves: Vec<Move>) -> Self {
+        Replay::from_snapshot(
+            Snapshot {
+                position: 0,
+                warehouse,
+            },
+            moves,
+        )
+    }
+
+    pub fn from_snapshot(snapshot: Snapshot, moves: Vec<Move>) -> Self {
+        assert!(
+            snapshot.position <= moves.len(),
+            "snapshot is past the last move"
+        );
+        Replay {
+            warehouse: snapshot.warehouse,
+            moves,
+            start: snapshot.position,
+            log: Vec::new(),
+        }
+    }
+
+    pub fn warehouse(&self) -> &Warehouse {
+        &self.warehouse
+    }
+
+    // Number of moves applied so far.
+    pub fn position(&self) -> usize {
+        self.start + self.log.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.moves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
+    // The logged steps, the first one being move number `start`.
+    pub fn log(&self) -> &[Step] {
+        &self.log
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            position: self.position(),
+            warehouse: self.warehouse.clone(),
+        }
+    }
+
+    pub fn forward(&mut self) -> Option<&Step> {
+        let direction = *self.moves.get(self.position())?;
+        let step = self.warehouse.step(direction);
+        self.log.push(step);
+        self.log.last()
+    }
+
+    pub fn back(&mut self) -> Option<Step> {
+        let step = self.log.pop()?;
+        self.warehouse.undo(&step);
+        Some(step)
+    }
+
+    // Go forwards or backwards until `position` moves have been applied, clamped to the moves
+    // there are and to the snapshot this replay started from.
+    pub fn jump_to(&mut self, position: usize) {
+        while self.position() < position && self.forward().is_some() {}
+        while self.position() > position && self.back().is_some() {}
+    }
+
+    // Step forward until `check` fails, returning the position of the move that broke it.
+    pub fn find_violation(&mut self, check: impl Fn(&Warehouse) -> bool) -> Option<usize> {
+        while self.forward().is_some() {
+            if !check(&self.warehouse) {
+                return Some(self.position());
             }
         }
+        None
     }
-    true
+
+    // One line per logged step: the move number (counting from 1), then the step.
+    pub fn export_log(&self) -> String {
+        self.log
+            .iter()
+            .enumerate()
+            .map(|(i, step)| format!("{}\t{}\n", self.start + i + 1, step))
+            .collect()
+    }
+}
+
+// The moves are all lines after the first empty one, joined.
+pub fn parse_moves(lines: &[String]) -> Vec<Move> {
+    lines
+        .iter()
+        .skip_while(|line| !line.is_empty())
+        .flat_map(|line| line.chars())
+        .map(Move::from_char)
+        .collect()
 }
 
-fn print_map(map_lines: &Vec<Vec<Object>>) {
-    for line in map_lines.iter() {
-        println!("{}", line.iter().map(|o| o.to_char()).collect::<String>());
+pub fn day15(test_input: Option<Vec<String>>, scale: usize) -> usize {
+    let lines = test_input.unwrap_or_else(|| aoc_read_day_lines(15));
+    let mut warehouse = Warehouse::from_lines(&lines, scale);
+    for m in parse_moves(&lines) {
+        warehouse.step(m);
     }
+    warehouse.gps_sum()
 }
 
-fn in_bounds(pos: (isize, isize), map_lines: &Vec<Vec<Object>>) -> bool {
-    pos.0 >= 0
-        && pos.0 < map_lines[0].len() as isize
-        && pos.1 >= 0
-        && pos.1 < map_lines.len() as isize
+pub fn day15_part1(test_input: Option<Vec<String>>) -> usize {
+    day15(test_input, 1)
+}
+
+pub fn day15_part2(test_input: Option<Vec<String>>) -> usize {
+    day15(test_input, 2)
 }
 
 #[cfg(test)]
@@ -570,4 +517,211 @@ v^^>>><<^^<>>^v^<v^vv<>v^<<>^<^v^v><^<<<><<^<v><v<>vv>>v><v^<vv<>v^<<^";
             9021
         );
     }
+
+    const SMALL_EXAMPLE: &str = "########
+#..O.O.#
+##@.O..#
+#...O..#
+#.#.O..#
+#...O..#
+#......#
+########
+
+<^^>>>vv<v>>v<<";
+
+    fn lines(s: &str) -> Vec<String> {
+        s.lines().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_small_example() {
+        assert_eq!(day15_part1(Some(lines(SMALL_EXAMPLE))), 2028);
+        let mut warehouse = Warehouse::from_lines(&lines(SMALL_EXAMPLE), 1);
+        for m in parse_moves(&lines(SMALL_EXAMPLE)) {
+            warehouse.step(m);
+        }
+        assert_eq!(
+            warehouse.to_string(),
+            "########
+#....OO#
+##.....#
+#.....O#
+#.#O@..#
+#...O..#
+#...O..#
+########
+"
+        );
+    }
+
+    #[test]
+    fn test_wide_scale() {
+        let input = lines(
+            "#######
+#...#.#
+#.....#
+#..OO@#
+#..O..#
+#.....#
+#######
+
+<vv<<^^<<^^",
+        );
+        let mut warehouse = Warehouse::from_lines(&input, 2);
+        assert_eq!(
+            warehouse.to_string(),
+            "##############
+##......##..##
+##..........##
+##....[][]@.##
+##....[]....##
+##..........##
+##############
+"
+        );
+        let step = warehouse.step(Move::Left);
+        assert!(step.moved());
+        assert_eq!(step.from, (10, 3));
+        assert_eq!(step.to, (9, 3));
+        assert_eq!(step.pushed, vec![1, 0]);
+        for m in parse_moves(&input) {
+            warehouse.step(m);
+        }
+        assert_eq!(
+            warehouse.to_string(),
+            "##############
+##..@.[]##..##
+##....[]....##
+##[]........##
+##..........##
+##..........##
+##############
+"
+        );
+        assert_eq!(warehouse.gps_sum(), 106 + 206 + 302);
+    }
+
+    #[test]
+    fn test_arbitrary_widths() {
+        // a three-wide box on top of two one-wide ones: pushing the left one up moves the wide
+        // box, which would have to push the wall above the middle
+        let mut warehouse = Warehouse::from_lines(
+            &lines(
+                "#######
+##.#..#
+#[=]..#
+#.OO..#
+#..@..#
+#######",
+            ),
+            1,
+        );
+        assert_eq!(warehouse.boxes[0].width, 3);
+        let blocked = warehouse.step(Move::Up);
+        assert!(!blocked.moved());
+        assert!(blocked.pushed.is_empty());
+        assert_eq!(warehouse.robot, (3, 4));
+
+        warehouse.step(Move::Right);
+        let step = warehouse.step(Move::Up);
+        assert_eq!(step.to, (4, 3));
+        assert!(step.pushed.is_empty());
+        let step = warehouse.step(Move::Left);
+        assert_eq!(step.pushed, vec![2, 1]);
+        assert_eq!(
+            warehouse.to_string(),
+            "#######
+##.#..#
+#[=]..#
+#OO@..#
+#.....#
+#######
+"
+        );
+    }
+
+    #[test]
+    fn test_gps_shared() {
+        let input = lines(EXAMPLE);
+        let mut narrow = Warehouse::from_lines(&input, 1);
+        let mut wide = Warehouse::from_lines(&input, 2);
+        assert_eq!(narrow.boxes.len(), wide.boxes.len());
+        assert_eq!(wide.width, 2 * narrow.width);
+        for m in parse_moves(&input) {
+            narrow.step(m);
+            wide.step(m);
+        }
+        assert_eq!(narrow.gps_sum(), 10092);
+        assert_eq!(wide.gps_sum(), 9021);
+    }
+
+    #[test]
+    fn test_undo_and_jump() {
+        let input = lines(EXAMPLE);
+        let initial = Warehouse::from_lines(&input, 2);
+        let mut replay = Replay::new(initial.clone(), parse_moves(&input));
+        assert_eq!(replay.len(), 700);
+        replay.jump_to(replay.len());
+        assert_eq!(replay.warehouse().gps_sum(), 9021);
+        let end = replay.warehouse().clone();
+
+        replay.jump_to(300);
+        assert_eq!(replay.position(), 300);
+        let middle = replay.warehouse().clone();
+        assert!(middle.is_consistent());
+        assert!(replay.back().is_some());
+        assert!(replay.forward().is_some());
+        assert_eq!(replay.warehouse(), &middle);
+
+        replay.jump_to(0);
+        assert_eq!(replay.warehouse(), &initial);
+        assert!(replay.back().is_none());
+        replay.jump_to(usize::MAX);
+        assert_eq!(replay.position(), 700);
+        assert_eq!(replay.warehouse(), &end);
+    }
+
+    #[test]
+    fn test_snapshot_replay() {
+        let input = lines(EXAMPLE);
+        let moves = parse_moves(&input);
+        let mut replay = Replay::new(Warehouse::from_lines(&input, 2), moves.clone());
+        replay.jump_to(250);
+        let snapshot = replay.snapshot();
+        replay.jump_to(moves.len());
+
+        let mut resumed = Replay::from_snapshot(snapshot.clone(), moves);
+        assert_eq!(resumed.position(), 250);
+        resumed.jump_to(400);
+        resumed.jump_to(0);
+        assert_eq!(resumed.position(), 250);
+        assert_eq!(resumed.warehouse(), &snapshot.warehouse);
+        resumed.jump_to(resumed.len());
+        assert_eq!(resumed.warehouse(), replay.warehouse());
+        assert_eq!(resumed.log(), &replay.log()[250..]);
+    }
+
+    #[test]
+    fn test_export_log_and_violations() {
+        let input = lines(SMALL_EXAMPLE);
+        let mut replay = Replay::new(Warehouse::from_lines(&input, 1), parse_moves(&input));
+        assert_eq!(replay.find_violation(Warehouse::is_consistent), None);
+        let log = replay.export_log();
+        assert_eq!(log.lines().count(), 15);
+        assert_eq!(
+            log.lines().take(4).collect::<Vec<_>>(),
+            vec![
+                "1\t< (2,2) blocked",
+                "2\t^ (2,2)->(2,1)",
+                "3\t^ (2,1) blocked",
+                "4\t> (2,1)->(3,1) pushed 0",
+            ]
+        );
+
+        // a check that box 0 never leaves its column finds the first push
+        replay.jump_to(0);
+        assert_eq!(replay.find_violation(|w| w.boxes[0].x == 3), Some(4));
+        assert_eq!(replay.position(), 4);
+        assert_eq!(replay.log()[3].pushed, vec![0]);
+    }
 }
@@ -1,4 +1,8 @@
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
+use std::io::Write;
+use std::ops::Range;
+
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 
 use crate::util::aoc_read_day_lines;
 
@@ -36,6 +40,111 @@ impl From<Insn> for u64 {
     }
 }
 
+// Register values and pc before an instruction executed, plus how much output existed at that point
+// so that stepping backwards can truncate it again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Snapshot {
+    pub pc: usize,
+    pub reg_a: u64,
+    pub reg_b: u64,
+    pub reg_c: u64,
+    pub output_len: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct Trace {
+    pub states: Vec<Snapshot>,
+    // reg_a, reg_b, reg_c, pc
+    visited: HashSet<(u64, u64, u64, usize)>,
+}
+
+impl Trace {
+    fn has_visited(&self, s: &Snapshot) -> bool {
+        self.visited.contains(&(s.reg_a, s.reg_b, s.reg_c, s.pc))
+    }
+
+    fn push(&mut self, s: Snapshot) {
+        self.visited.insert((s.reg_a, s.reg_b, s.reg_c, s.pc));
+        self.states.push(s);
+    }
+
+    fn pop(&mut self) -> Option<Snapshot> {
+        let s = self.states.pop()?;
+        self.visited.remove(&(s.reg_a, s.reg_b, s.reg_c, s.pc));
+        Some(s)
+    }
+
+    // The operand column is empty for an opcode at the very end of the program.
+    pub fn to_csv(&self, insns: &[Insn]) -> String {
+        let mut csv = String::from("step,pc,insn,operand,reg_a,reg_b,reg_c,output_len\n");
+        for (step, s) in self.states.iter().enumerate() {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{}\n",
+                step,
+                s.pc,
+                insns
+                    .get(s.pc)
+                    .map_or(String::new(), |insn| format!("{:?}", insn)),
+                insns
+                    .get(s.pc + 1)
+                    .map_or(String::new(), |&operand| (operand as u64).to_string()),
+                s.reg_a,
+                s.reg_b,
+                s.reg_c,
+                s.output_len
+            ));
+        }
+        csv
+    }
+}
+
+// a / 2^n, which is 0 once the shift is as wide as the register
+fn dv(a: u64, n: u64) -> u64 {
+    u32::try_from(n)
+        .ok()
+        .and_then(|n| a.checked_shr(n))
+        .unwrap_or(0)
+}
+
+// Brent's cycle detection over a sequence of machine states: a state is saved at every power of
+// two steps, and a program that loops forever comes back to the saved state within one cycle
+// length once the saved state is inside the cycle. Constant memory, unlike remembering every
+// state.
+struct LoopDetector<T> {
+    saved: Option<T>,
+    power: u64,
+    steps: u64,
+}
+
+impl<T: PartialEq + Copy> LoopDetector<T> {
+    fn new() -> Self {
+        LoopDetector {
+            saved: None,
+            power: 1,
+            steps: 0,
+        }
+    }
+
+    // Whether `state` was seen before. Only a program that loops forever ever repeats a state.
+    #[inline(always)]
+    fn repeats(&mut self, state: T) -> bool {
+        if self.saved == Some(state) {
+            return true;
+        }
+        self.steps += 1;
+        if self.steps == self.power {
+            self.saved = Some(state);
+            self.power *= 2;
+            self.steps = 0;
+        }
+        false
+    }
+}
+
+// The program reached a state it was in before, so it never halts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InfiniteLoop;
+
 pub struct ProgramState {
     pub reg_a: u64,
     pub reg_b: u64,
@@ -43,43 +152,83 @@ pub struct ProgramState {
     pub pc: usize,
     pub insns: Vec<Insn>,
     pub output: Vec<u64>,
-    // reg_a, reg_b, reg_c, pc
-    pub past_states: Option<HashSet<(u64, u64, u64, usize)>>,
+    // ordered history of executed states; revisiting a state means the program loops forever
+    pub trace: Option<Trace>,
+    // stop as soon as the output diverges from the program itself
+    pub expect_quine: bool,
 }
 
 impl ProgramState {
-    fn run(&mut self) -> Option<Vec<u64>> {
-        if let Some(past_states) = &self.past_states {
-            if past_states.contains(&(self.reg_a, self.reg_b, self.reg_c, self.pc)) {
+    // The output once the program halts. None if it loops forever, or with `expect_quine` as soon
+    // as the output stops matching the program. Without a trace, loops are found by
+    // LoopDetector.
+    pub fn run(&mut self) -> Option<Vec<u64>> {
+        let mut detector = LoopDetector::new();
+        loop {
+            if self.is_halted() {
+                return Some(self.output.clone());
+            }
+            let continue_execution = self.step();
+            if !continue_execution
+                || (self.trace.is_none()
+                    && detector.repeats((self.pc, self.reg_a, self.reg_b, self.reg_c)))
+            {
                 return None;
             }
         }
-        if let Some(past_states) = &mut self.past_states {
-            past_states.insert((self.reg_a, self.reg_b, self.reg_c, self.pc));
+    }
+
+    // Halted once there is no whole instruction left at pc, including an opcode without its
+    // operand at the very end.
+    pub fn is_halted(&self) -> bool {
+        self.pc + 1 >= self.insns.len()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            pc: self.pc,
+            reg_a: self.reg_a,
+            reg_b: self.reg_b,
+            reg_c: self.reg_c,
+            output_len: self.output.len(),
         }
-        loop {
-            println!(
-                "Step: pc={}, reg_a={}, reg_b={}, reg_c={}, output={:?}",
-                self.pc, self.reg_a, self.reg_b, self.reg_c, self.output
-            );
+    }
 
-            if self.pc == self.insns.len() {
-                return Some(self.output.clone());
+    pub fn step(&mut self) -> bool {
+        let before = self.snapshot();
+        if let Some(trace) = &self.trace {
+            if trace.has_visited(&before) {
+                return false;
             }
-            let continue_execution = self.step();
-            if !continue_execution {
-                return None;
+        }
+        let continue_execution = self.execute(self.insns[self.pc]);
+        if continue_execution {
+            if let Some(trace) = &mut self.trace {
+                trace.push(before);
             }
         }
+        continue_execution
     }
 
-    fn step(&mut self) -> bool {
-        let insn = self.insns[self.pc];
+    // Undoes the last traced step. Returns false if there is no trace or nothing to undo.
+    pub fn step_back(&mut self) -> bool {
+        let Some(s) = self.trace.as_mut().and_then(|trace| trace.pop()) else {
+            return false;
+        };
+        self.pc = s.pc;
+        self.reg_a = s.reg_a;
+        self.reg_b = s.reg_b;
+        self.reg_c = s.reg_c;
+        self.output.truncate(s.output_len);
+        true
+    }
+
+    fn execute(&mut self, insn: Insn) -> bool {
         match insn {
             Insn::Adv => {
                 let numerator = self.reg_a;
                 let operand = self.combo_operand(self.insns[self.pc + 1] as u8);
-                self.reg_a = numerator / (1 << operand);
+                self.reg_a = dv(numerator, operand);
                 self.pc += 2;
                 true
             }
@@ -116,7 +265,9 @@ impl ProgramState {
             Insn::Out => {
                 let combo_operand = self.combo_operand(self.insns[self.pc + 1] as u8);
                 let result = combo_operand % 8;
-                if self.past_states.is_some() && result != self.insns[self.output.len()] as u64 {
+                if self.expect_quine
+                    && self.insns.get(self.output.len()).map(|&i| i as u64) != Some(result)
+                {
                     return false;
                 }
                 self.output.push(result);
@@ -126,14 +277,14 @@ impl ProgramState {
             Insn::Bdv => {
                 let numerator = self.reg_a;
                 let operand = self.combo_operand(self.insns[self.pc + 1] as u8);
-                self.reg_b = numerator / (1 << operand);
+                self.reg_b = dv(numerator, operand);
                 self.pc += 2;
                 true
             }
             Insn::Cdv => {
                 let numerator = self.reg_a;
                 let operand = self.combo_operand(self.insns[self.pc + 1] as u8);
-                self.reg_c = numerator / (1 << operand);
+                self.reg_c = dv(numerator, operand);
                 self.pc += 2;
                 true
             }
@@ -173,7 +324,6 @@ pub fn parse_program(lines: Vec<String>, record_history: bool) -> ProgramState {
         .split_whitespace()
         .nth(1)
         .unwrap();
-    println!("program_line: {}", program_line);
     let program = program_line
         .split(',')
         .map(|s| {
@@ -183,8 +333,6 @@ pub fn parse_program(lines: Vec<String>, record_history: bool) -> ProgramState {
         })
         .collect::<Vec<_>>();
 
-    println!("program: {:?}", program);
-
     ProgramState {
         reg_a: regs[0],
         reg_b: regs[1],
@@ -192,11 +340,12 @@ pub fn parse_program(lines: Vec<String>, record_history: bool) -> ProgramState {
         pc: 0,
         insns: program,
         output: vec![],
-        past_states: if record_history {
-            Some(HashSet::new())
+        trace: if record_history {
+            Some(Trace::default())
         } else {
             None
         },
+        expect_quine: false,
     }
 }
 
@@ -272,9 +421,515 @@ pub fn day17_part2(test_input: Option<String>) -> usize {
     final_answer as usize
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
+    A,
+    B,
+    C,
+}
+
+impl Register {
+    fn from_str(s: &str) -> Option<Register> {
+        match s {
+            "a" | "A" => Some(Register::A),
+            "b" | "B" => Some(Register::B),
+            "c" | "C" => Some(Register::C),
+            _ => None,
+        }
+    }
+
+    fn read(self, state: &ProgramState) -> u64 {
+        match self {
+            Register::A => state.reg_a,
+            Register::B => state.reg_b,
+            Register::C => state.reg_c,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Stepped,
+    Breakpoint(usize),
+    // register, old value, new value
+    Watch(Register, u64, u64),
+    Halted,
+    Looped,
+    StartOfTrace,
+}
+
+pub struct Debugger {
+    pub state: ProgramState,
+    breakpoints: BTreeSet<usize>,
+    watches: Vec<Register>,
+}
+
+impl Debugger {
+    pub fn new(mut state: ProgramState) -> Self {
+        if state.trace.is_none() {
+            state.trace = Some(Trace::default());
+        }
+        Debugger {
+            state,
+            breakpoints: BTreeSet::new(),
+            watches: vec![],
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, pc: usize) {
+        self.breakpoints.insert(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: usize) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    pub fn watch(&mut self, reg: Register) {
+        if !self.watches.contains(&reg) {
+            self.watches.push(reg);
+        }
+    }
+
+    pub fn unwatch(&mut self, reg: Register) {
+        self.watches.retain(|&r| r != reg);
+    }
+
+    fn watched_values(&self) -> Vec<u64> {
+        self.watches.iter().map(|r| r.read(&self.state)).collect()
+    }
+
+    // The first watched register that no longer holds its value from `before`.
+    fn watch_hit(&self, before: Vec<u64>) -> Option<StopReason> {
+        self.watches.iter().zip(before).find_map(|(reg, old)| {
+            let new = reg.read(&self.state);
+            (new != old).then_some(StopReason::Watch(*reg, old, new))
+        })
+    }
+
+    pub fn step(&mut self) -> StopReason {
+        if self.state.is_halted() {
+            return StopReason::Halted;
+        }
+        let before = self.watched_values();
+        if !self.state.step() {
+            return StopReason::Looped;
+        }
+        if let Some(hit) = self.watch_hit(before) {
+            return hit;
+        }
+        if self.state.is_halted() {
+            return StopReason::Halted;
+        }
+        if self.breakpoints.contains(&self.state.pc) {
+            return StopReason::Breakpoint(self.state.pc);
+        }
+        StopReason::Stepped
+    }
+
+    pub fn continue_execution(&mut self) -> StopReason {
+        loop {
+            let reason = self.step();
+            if reason != StopReason::Stepped {
+                return reason;
+            }
+        }
+    }
+
+    // Watches fire going backwards too, with the values in the order they are seen.
+    pub fn reverse_step(&mut self) -> StopReason {
+        let before = self.watched_values();
+        if !self.state.step_back() {
+            return StopReason::StartOfTrace;
+        }
+        if let Some(hit) = self.watch_hit(before) {
+            return hit;
+        }
+        if self.breakpoints.contains(&self.state.pc) {
+            return StopReason::Breakpoint(self.state.pc);
+        }
+        StopReason::Stepped
+    }
+
+    pub fn trace_csv(&self) -> String {
+        match &self.state.trace {
+            Some(trace) => trace.to_csv(&self.state.insns),
+            None => String::new(),
+        }
+    }
+
+    pub fn status(&self) -> String {
+        let s = &self.state;
+        let insn = match (s.insns.get(s.pc), s.insns.get(s.pc + 1)) {
+            (Some(insn), Some(&operand)) => format!("{:?} {}", insn, operand as u64),
+            // an opcode without its operand, which halts
+            (Some(insn), None) => format!("{:?} - halted", insn),
+            (None, _) => "halted".to_string(),
+        };
+        let watched = self
+            .watches
+            .iter()
+            .map(|r| format!(" {:?}={}", r, r.read(s)))
+            .collect::<String>();
+        format!(
+            "pc={} {} | a={} b={} c={} | out={}{}",
+            s.pc,
+            insn,
+            s.reg_a,
+            s.reg_b,
+            s.reg_c,
+            s.output
+                .iter()
+                .map(|o| o.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+            if watched.is_empty() {
+                String::new()
+            } else {
+                format!(" | watch:{}", watched)
+            }
+        )
+    }
+
+    // Runs a single debugger command and returns the text to show. None means quit.
+    pub fn execute(&mut self, command: &str) -> Option<String> {
+        let mut parts = command.split_whitespace();
+        let Some(cmd) = parts.next() else {
+            return Some(self.status());
+        };
+        let arg = parts.next();
+        let count = || arg.and_then(|a| a.parse::<usize>().ok()).unwrap_or(1);
+        let reason = match cmd {
+            "s" | "step" => {
+                let mut reason = StopReason::Stepped;
+                for _ in 0..count() {
+                    reason = self.step();
+                    if reason != StopReason::Stepped {
+                        break;
+                    }
+                }
+                reason
+            }
+            "r" | "back" => {
+                let mut reason = StopReason::Stepped;
+                for _ in 0..count() {
+                    reason = self.reverse_step();
+                    if reason != StopReason::Stepped {
+                        break;
+                    }
+                }
+                reason
+            }
+            "c" | "continue" => self.continue_execution(),
+            "b" | "break" => {
+                return Some(match arg.and_then(|a| a.parse().ok()) {
+                    Some(pc) => {
+                        self.add_breakpoint(pc);
+                        format!("breakpoint at pc={}", pc)
+                    }
+                    None => "usage: break <pc>".to_string(),
+                });
+            }
+            "d" | "delete" => {
+                return Some(match arg.and_then(|a| a.parse().ok()) {
+                    Some(pc) if self.remove_breakpoint(pc) => {
+                        format!("removed breakpoint at pc={}", pc)
+                    }
+                    Some(pc) => format!("no breakpoint at pc={}", pc),
+                    None => "usage: delete <pc>".to_string(),
+                });
+            }
+            "w" | "watch" => {
+                return Some(match arg.and_then(Register::from_str) {
+                    Some(reg) => {
+                        self.watch(reg);
+                        format!("watching {:?}", reg)
+                    }
+                    None => "usage: watch <a|b|c>".to_string(),
+                });
+            }
+            "unwatch" => {
+                return Some(match arg.and_then(Register::from_str) {
+                    Some(reg) => {
+                        self.unwatch(reg);
+                        format!("no longer watching {:?}", reg)
+                    }
+                    None => "usage: unwatch <a|b|c>".to_string(),
+                });
+            }
+            "p" | "regs" => return Some(self.status()),
+            "dump" => {
+                return Some(match arg {
+                    Some(path) => match std::fs::write(path, self.trace_csv()) {
+                        Ok(()) => format!("wrote trace to {}", path),
+                        Err(e) => format!("failed to write {}: {}", path, e),
+                    },
+                    None => self.trace_csv(),
+                });
+            }
+            "q" | "quit" => return None,
+            _ => {
+                return Some(
+                    "commands: step [n], back [n], continue, break <pc>, delete <pc>, watch <reg>, unwatch <reg>, regs, dump [path], quit"
+                        .to_string(),
+                );
+            }
+        };
+        let prefix = match reason {
+            StopReason::Stepped => String::new(),
+            StopReason::Breakpoint(pc) => format!("breakpoint at pc={}\n", pc),
+            StopReason::Watch(reg, old, new) => format!("{:?} changed {} -> {}\n", reg, old, new),
+            StopReason::Halted => "program halted\n".to_string(),
+            StopReason::Looped => "program loops forever from here\n".to_string(),
+            StopReason::StartOfTrace => "at start of trace\n".to_string(),
+        };
+        Some(format!("{}{}", prefix, self.status()))
+    }
+}
+
+pub fn day17_debug(test_input: Option<String>) {
+    let lines = if let Some(input) = test_input {
+        input.lines().map(|s| s.to_string()).collect()
+    } else {
+        aoc_read_day_lines(17)
+    };
+
+    let mut debugger = Debugger::new(parse_program(lines, true));
+    println!("{}", debugger.status());
+    let stdin = std::io::stdin();
+    loop {
+        print!("(d17) ");
+        std::io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        match debugger.execute(&line) {
+            Some(out) => println!("{}", out),
+            None => break,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Operand {
+    Literal(u64),
+    A,
+    B,
+    C,
+    Reserved,
+}
+
+impl Operand {
+    fn combo(operand: Insn) -> Self {
+        match operand as u64 {
+            n @ 0..=3 => Operand::Literal(n),
+            4 => Operand::A,
+            5 => Operand::B,
+            6 => Operand::C,
+            _ => Operand::Reserved,
+        }
+    }
+
+    #[inline(always)]
+    fn read(self, a: u64, b: u64, c: u64) -> u64 {
+        match self {
+            Operand::Literal(n) => n,
+            Operand::A => a,
+            Operand::B => b,
+            Operand::C => c,
+            Operand::Reserved => panic!("Invalid operand"),
+        }
+    }
+}
+
+// An instruction with its operand already decoded
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Adv(Operand),
+    Bxl(u64),
+    Bst(Operand),
+    Jnz(usize),
+    Bxc,
+    Out(Operand),
+    Bdv(Operand),
+    Cdv(Operand),
+}
+
+impl Op {
+    fn decode(insn: Insn, operand: Insn) -> Self {
+        match insn {
+            Insn::Adv => Op::Adv(Operand::combo(operand)),
+            Insn::Bxl => Op::Bxl(operand as u64),
+            Insn::Bst => Op::Bst(Operand::combo(operand)),
+            Insn::Jnz => Op::Jnz(operand as usize),
+            Insn::Bxc => Op::Bxc,
+            Insn::Out => Op::Out(Operand::combo(operand)),
+            Insn::Bdv => Op::Bdv(Operand::combo(operand)),
+            Insn::Cdv => Op::Cdv(Operand::combo(operand)),
+        }
+    }
+
+    // Executes everything except jumps, returning the output value for Out.
+    #[inline(always)]
+    fn exec(self, a: &mut u64, b: &mut u64, c: &mut u64) -> Option<u64> {
+        match self {
+            Op::Adv(op) => *a = dv(*a, op.read(*a, *b, *c)),
+            Op::Bxl(n) => *b ^= n,
+            Op::Bst(op) => *b = op.read(*a, *b, *c) % 8,
+            Op::Jnz(_) => unreachable!("jumps are handled by the caller"),
+            Op::Bxc => *b ^= *c,
+            Op::Out(op) => return Some(op.read(*a, *b, *c) % 8),
+            Op::Bdv(op) => *b = dv(*a, op.read(*a, *b, *c)),
+            Op::Cdv(op) => *c = dv(*a, op.read(*a, *b, *c)),
+        }
+        None
+    }
+}
+
+// A program decoded once up front, for evaluating it with many different initial A values.
+pub struct CompiledProgram {
+    // decoded instruction starting at each pc, so odd jump targets work too
+    ops: Vec<Op>,
+    // set when the program is `<straight-line code with exactly one out>, jnz 0`, i.e. it emits
+    // one value per iteration and can be run without tracking pc at all
+    loop_body: Option<Vec<Op>>,
+    program: Vec<u64>,
+    reg_b: u64,
+    reg_c: u64,
+}
+
+impl CompiledProgram {
+    pub fn compile(state: &ProgramState) -> Self {
+        let insns = &state.insns;
+        let ops = insns
+            .windows(2)
+            .map(|w| Op::decode(w[0], w[1]))
+            .collect::<Vec<_>>();
+
+        let body_len = insns.len().saturating_sub(2);
+        let is_single_loop = insns.len().is_multiple_of(2)
+            && body_len > 0
+            && matches!(ops[body_len], Op::Jnz(0))
+            && (0..body_len)
+                .step_by(2)
+                .all(|pc| !matches!(ops[pc], Op::Jnz(_)))
+            && (0..body_len)
+                .step_by(2)
+                .filter(|&pc| matches!(ops[pc], Op::Out(_)))
+                .count()
+                == 1;
+        let loop_body = if is_single_loop {
+            Some((0..body_len).step_by(2).map(|pc| ops[pc]).collect())
+        } else {
+            None
+        };
+
+        CompiledProgram {
+            ops,
+            loop_body,
+            program: insns.iter().map(|&i| i as u64).collect(),
+            reg_b: state.reg_b,
+            reg_c: state.reg_c,
+        }
+    }
+
+    pub fn is_single_loop(&self) -> bool {
+        self.loop_body.is_some()
+    }
+
+    // Feeds outputs to `f` until the program halts or `f` returns false. The single loop checks
+    // for a repeated state once per iteration, the general path once per jump.
+    #[inline(always)]
+    fn for_each_output(
+        &self,
+        reg_a: u64,
+        mut f: impl FnMut(u64) -> bool,
+    ) -> Result<(), InfiniteLoop> {
+        let (mut a, mut b, mut c) = (reg_a, self.reg_b, self.reg_c);
+        if let Some(body) = &self.loop_body {
+            let mut detector = LoopDetector::new();
+            loop {
+                for op in body {
+                    if let Some(out) = op.exec(&mut a, &mut b, &mut c) {
+                        if !f(out) {
+                            return Ok(());
+                        }
+                    }
+                }
+                if a == 0 {
+                    return Ok(());
+                }
+                if detector.repeats((a, b, c)) {
+                    return Err(InfiniteLoop);
+                }
+            }
+        }
+
+        let mut detector = LoopDetector::new();
+        let mut pc = 0;
+        while pc < self.ops.len() {
+            match self.ops[pc] {
+                Op::Jnz(target) => {
+                    if a == 0 {
+                        pc += 2;
+                        continue;
+                    }
+                    // every loop goes through a taken jump
+                    if detector.repeats((pc, a, b, c)) {
+                        return Err(InfiniteLoop);
+                    }
+                    pc = target;
+                }
+                op => {
+                    if let Some(out) = op.exec(&mut a, &mut b, &mut c) {
+                        if !f(out) {
+                            return Ok(());
+                        }
+                    }
+                    pc += 2;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn run(&self, reg_a: u64) -> Result<Vec<u64>, InfiniteLoop> {
+        let mut output = vec![];
+        self.for_each_output(reg_a, |out| {
+            output.push(out);
+            true
+        })?;
+        Ok(output)
+    }
+
+    // Whether the program prints itself and halts, bailing out at the first mismatching output.
+    pub fn outputs_program(&self, reg_a: u64) -> bool {
+        let mut i = 0;
+        let mut matches = true;
+        let halted = self.for_each_output(reg_a, |out| {
+            matches = self.program.get(i) == Some(&out);
+            i += 1;
+            matches
+        });
+        halted.is_ok() && matches && i == self.program.len()
+    }
+
+    pub fn eval_batch(&self, reg_a_values: &[u64]) -> Vec<Result<Vec<u64>, InfiniteLoop>> {
+        reg_a_values.par_iter().map(|&a| self.run(a)).collect()
+    }
+
+    // Smallest A in the range for which the program outputs itself.
+    pub fn find_quine(&self, reg_a_range: Range<u64>) -> Option<u64> {
+        reg_a_range
+            .into_par_iter()
+            .find_first(|&a| self.outputs_program(a))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::util::test_rng;
 
     #[test]
     fn test_reg_c_9() {
@@ -285,7 +940,8 @@ mod tests {
             pc: 0,
             insns: vec![Insn::from(2), Insn::from(6)],
             output: vec![],
-            past_states: None,
+            trace: None,
+            expect_quine: false,
         };
         state.step();
         assert_eq!(state.reg_b, 1);
@@ -307,7 +963,8 @@ mod tests {
                 Insn::from(4),
             ],
             output: vec![],
-            past_states: None,
+            trace: None,
+            expect_quine: false,
         };
         let output = state.run();
         assert_eq!(output, Some(vec![0, 1, 2]));
@@ -329,7 +986,8 @@ mod tests {
                 Insn::from(0),
             ],
             output: vec![],
-            past_states: None,
+            trace: None,
+            expect_quine: false,
         };
         let output = state.run();
         assert_eq!(output, Some(vec![4, 2, 5, 6, 7, 7, 7, 7, 3, 1, 0]));
@@ -345,7 +1003,8 @@ mod tests {
             pc: 0,
             insns: vec![Insn::from(1), Insn::from(7)],
             output: vec![],
-            past_states: None,
+            trace: None,
+            expect_quine: false,
         };
         state.run();
         assert_eq!(state.reg_b, 26);
@@ -360,7 +1019,8 @@ mod tests {
             pc: 0,
             insns: vec![Insn::from(4), Insn::from(0)],
             output: vec![],
-            past_states: None,
+            trace: None,
+            expect_quine: false,
         };
         state.run();
         assert_eq!(state.reg_b, 44354);
@@ -382,4 +1042,253 @@ Program: 0,3,5,4,3,0";
         let ret = day17_part2(Some(EXAMPLE.to_string()));
         assert_eq!(ret, 117440);
     }
+
+    const DEBUG_EXAMPLE: &str = "Register A: 729
+Register B: 0
+Register C: 0
+
+Program: 0,1,5,4,3,0";
+
+    #[test]
+    fn test_debugger_breakpoint_and_reverse_step() {
+        let lines = DEBUG_EXAMPLE.lines().map(|s| s.to_string()).collect();
+        let mut debugger = Debugger::new(parse_program(lines, true));
+        debugger.add_breakpoint(4);
+        assert_eq!(debugger.continue_execution(), StopReason::Breakpoint(4));
+        assert_eq!(debugger.state.output, vec![4]);
+        assert_eq!(debugger.state.reg_a, 364);
+
+        assert_eq!(debugger.reverse_step(), StopReason::Stepped);
+        assert_eq!(debugger.state.pc, 2);
+        assert_eq!(debugger.state.output, vec![]);
+        assert_eq!(debugger.reverse_step(), StopReason::Stepped);
+        assert_eq!(debugger.state.reg_a, 729);
+        assert_eq!(debugger.reverse_step(), StopReason::StartOfTrace);
+
+        debugger.remove_breakpoint(4);
+        assert_eq!(debugger.continue_execution(), StopReason::Halted);
+        assert_eq!(debugger.state.output, vec![4, 6, 3, 5, 6, 3, 5, 2, 1, 0]);
+    }
+
+    #[test]
+    fn test_debugger_watch_and_csv() {
+        let lines = DEBUG_EXAMPLE.lines().map(|s| s.to_string()).collect();
+        let mut debugger = Debugger::new(parse_program(lines, true));
+        debugger.watch(Register::A);
+        assert_eq!(
+            debugger.continue_execution(),
+            StopReason::Watch(Register::A, 729, 364)
+        );
+        assert_eq!(
+            debugger.execute("step 2").unwrap(),
+            "pc=0 Adv 1 | a=364 b=0 c=0 | out=4 | watch: A=364"
+        );
+
+        let csv = debugger.trace_csv();
+        let rows = csv.lines().collect::<Vec<_>>();
+        assert_eq!(rows[0], "step,pc,insn,operand,reg_a,reg_b,reg_c,output_len");
+        assert_eq!(rows[1], "0,0,Adv,1,729,0,0,0");
+        assert_eq!(rows.len(), 4);
+    }
+
+    #[test]
+    fn test_infinite_loop_detected() {
+        let mut state = ProgramState {
+            reg_a: 1,
+            reg_b: 0,
+            reg_c: 0,
+            pc: 0,
+            insns: vec![Insn::from(3), Insn::from(0)],
+            output: vec![],
+            trace: Some(Trace::default()),
+            expect_quine: false,
+        };
+        assert_eq!(state.run(), None);
+    }
+
+    #[test]
+    fn test_compiled_matches_interpreter() {
+        let lines = DEBUG_EXAMPLE.lines().map(|s| s.to_string()).collect();
+        let state = parse_program(lines, false);
+        let compiled = CompiledProgram::compile(&state);
+        assert!(compiled.is_single_loop());
+
+        let reg_a_values = (0..2000).map(|a| a * 37).collect::<Vec<_>>();
+        let batch = compiled.eval_batch(&reg_a_values);
+        for (&a, compiled_output) in reg_a_values.iter().zip(batch) {
+            let mut interpreted = ProgramState {
+                reg_a: a,
+                reg_b: 0,
+                reg_c: 0,
+                pc: 0,
+                insns: state.insns.clone(),
+                output: vec![],
+                trace: None,
+                expect_quine: false,
+            };
+            assert_eq!(interpreted.run(), compiled_output.ok());
+        }
+    }
+
+    #[test]
+    fn test_compiled_general_program() {
+        // jumps back to pc 2, so it does not fit the single loop shape
+        let state = ProgramState {
+            reg_a: 0,
+            reg_b: 0,
+            reg_c: 0,
+            pc: 0,
+            insns: [5, 4, 0, 1, 5, 4, 3, 2].map(Insn::from).to_vec(),
+            output: vec![],
+            trace: None,
+            expect_quine: false,
+        };
+        let compiled = CompiledProgram::compile(&state);
+        assert!(!compiled.is_single_loop());
+        assert_eq!(compiled.run(10), Ok(vec![2, 5, 2, 1, 0]));
+    }
+
+    #[test]
+    fn test_compiled_find_quine() {
+        let lines = "Register A: 2024
+Register B: 0
+Register C: 0
+
+Program: 0,3,5,4,3,0"
+            .lines()
+            .map(|s| s.to_string())
+            .collect();
+        let compiled = CompiledProgram::compile(&parse_program(lines, false));
+        assert_eq!(compiled.find_quine(0..200_000), Some(117440));
+    }
+
+    #[test]
+    fn test_loops_without_trace() {
+        // prints B and jumps back to the start without ever changing A
+        let insns = [1, 3, 5, 5, 3, 0].map(Insn::from).to_vec();
+        let mut state = ProgramState {
+            reg_a: 5,
+            reg_b: 0,
+            reg_c: 0,
+            pc: 0,
+            insns: insns.clone(),
+            output: vec![],
+            trace: None,
+            expect_quine: false,
+        };
+        let compiled = CompiledProgram::compile(&state);
+        assert_eq!(state.run(), None);
+        assert!(compiled.is_single_loop());
+        assert_eq!(compiled.run(5), Err(InfiniteLoop));
+        assert_eq!(compiled.run(0), Ok(vec![3]));
+        assert_eq!(compiled.find_quine(0..1000), None);
+
+        // the same through the general path: jump to pc 2, where B toggles forever
+        let state = ProgramState {
+            reg_b: 0,
+            insns: [1, 3, 1, 5, 5, 5, 3, 2].map(Insn::from).to_vec(),
+            ..state
+        };
+        let compiled = CompiledProgram::compile(&state);
+        assert!(!compiled.is_single_loop());
+        assert_eq!(
+            compiled.eval_batch(&[0, 7]),
+            vec![Ok(vec![6]), Err(InfiniteLoop)]
+        );
+    }
+
+    #[test]
+    fn test_interpreter_and_compiled_agree() {
+        let mut next = test_rng(17);
+        for _ in 0..3000 {
+            let len = 2 + next(10) as usize;
+            // opcodes at even positions and operands at odd ones. Jumps only go to even targets
+            // and operands are never the reserved 7, which both backends reject.
+            let mut values = (0..len)
+                .map(|i| next(if i % 2 == 0 { 8 } else { 7 }))
+                .collect::<Vec<_>>();
+            for i in (0..len - 1).step_by(2) {
+                if values[i] == 3 {
+                    values[i + 1] &= !1;
+                }
+            }
+            let insns = values.into_iter().map(Insn::from).collect::<Vec<_>>();
+            // shifts by B or C of 64 and more, and by values past u32
+            let wide = [0, 1, 63, 64, 65, 1 << 32, u64::MAX];
+            let reg_b = wide[next(wide.len() as u64) as usize];
+            let reg_c = wide[next(wide.len() as u64) as usize];
+            let reg_a = if next(2) == 0 {
+                next(1 << 20)
+            } else {
+                u64::MAX - next(1000)
+            };
+            let mut state = ProgramState {
+                reg_a,
+                reg_b,
+                reg_c,
+                pc: 0,
+                insns,
+                output: vec![],
+                trace: None,
+                expect_quine: false,
+            };
+            let compiled = CompiledProgram::compile(&state);
+            let insns = state.insns.clone();
+            assert_eq!(
+                state.run(),
+                compiled.run(reg_a).ok(),
+                "program {:?} with a={} b={} c={}",
+                insns,
+                reg_a,
+                reg_b,
+                reg_c
+            );
+        }
+    }
+
+    #[test]
+    fn test_debugger_odd_length_program() {
+        // jumps to the trailing opcode, which has no operand
+        let lines = "Register A: 1
+Register B: 0
+Register C: 0
+
+Program: 3,2,0"
+            .lines()
+            .map(|s| s.to_string())
+            .collect();
+        let mut debugger = Debugger::new(parse_program(lines, true));
+        assert_eq!(debugger.step(), StopReason::Halted);
+        assert_eq!(debugger.state.pc, 2);
+        assert_eq!(debugger.status(), "pc=2 Adv - halted | a=1 b=0 c=0 | out=");
+        assert_eq!(debugger.step(), StopReason::Halted);
+        let csv = debugger.trace_csv();
+        assert_eq!(csv.lines().nth(1), Some("0,0,Jnz,2,1,0,0,0"));
+
+        let trace = Trace {
+            states: vec![debugger.state.snapshot()],
+            ..Trace::default()
+        };
+        assert_eq!(
+            trace.to_csv(&debugger.state.insns).lines().nth(1),
+            Some("0,2,Adv,,1,0,0,0")
+        );
+    }
+
+    #[test]
+    fn test_debugger_reverse_watch() {
+        let lines = DEBUG_EXAMPLE.lines().map(|s| s.to_string()).collect();
+        let mut debugger = Debugger::new(parse_program(lines, true));
+        assert_eq!(debugger.execute("step 3").unwrap().lines().count(), 1);
+        assert_eq!(debugger.state.reg_a, 364);
+        debugger.watch(Register::A);
+        // out and jnz leave A alone, then undoing adv restores it
+        assert_eq!(debugger.reverse_step(), StopReason::Stepped);
+        assert_eq!(debugger.reverse_step(), StopReason::Stepped);
+        assert_eq!(
+            debugger.reverse_step(),
+            StopReason::Watch(Register::A, 364, 729)
+        );
+        assert_eq!(debugger.state.pc, 0);
+    }
 }
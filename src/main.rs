@@ -8,16 +8,45 @@ use aoc2024::day07::{day7_part1, day7_part2};
 use aoc2024::day08::{day8_part1, day8_part2};
 use aoc2024::day09::{day9_part1, day9_part2};
 use aoc2024::day10::{day10_part1, day10_part2};
-use aoc2024::day11::{day11_part1, day11_part2};
+use aoc2024::day11::{day11_part1, day11_part2, BigCount, StoneCounter};
 use aoc2024::day12::{day12_part1, day12_part2};
 use aoc2024::day13::{day13_part1, day13_part2};
 use aoc2024::day14::{day14_part1, day14_part2};
 use aoc2024::day15::{day15_part1, day15_part2};
 use aoc2024::day16::{day16_part1, day16_part2};
-use aoc2024::day17::{day17_part1, day17_part2};
+use aoc2024::day17::{day17_debug, day17_part1, day17_part2};
 use aoc2024::day18::{day18_part1, day18_part2};
 use aoc2024::day19::{day19_part1, day19_part2};
 fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    if args.get(1).map(|s| s.as_str()) == Some("debug17") {
+        // optional path to a program file, defaults to day17.txt
+        let input = args
+            .get(2)
+            .map(|path| std::fs::read_to_string(path).unwrap());
+        day17_debug(input);
+        return;
+    }
+    if args.get(1).map(|s| s.as_str()) == Some("stones") {
+        // blink count and optional path to the stones, defaults to day11.txt
+        let blinks = args
+            .get(2)
+            .and_then(|s| s.parse().ok())
+            .expect("usage: stones <blinks> [file]");
+        let line = args.get(3).map_or_else(
+            || std::fs::read_to_string("day11.txt").unwrap(),
+            |path| std::fs::read_to_string(path).unwrap(),
+        );
+        let mut counter = StoneCounter::<BigCount>::from_line(&line);
+        counter.blink_n(blinks);
+        println!(
+            "stones after {} blinks: {} ({} distinct values)",
+            blinks,
+            counter.total(),
+            counter.distinct()
+        );
+        return;
+    }
     // let ret1 = day1_part1();
     // println!("day1_part1: {}", ret1);
     // let ret1_2 = day1_part2();
@@ -30,45 +59,45 @@ fn main() {
     // println!("day3_part1: {}", ret3);
     // let ret3_2 = day3_part2();
     // println!("day3_part2: {}", ret3_2);
-    // let ret4 = day4_part1();
+    // let ret4 = day4_part1(None);
     // println!("day4_part1: {}", ret4);
-    // let ret4_2 = day4_part2();
+    // let ret4_2 = day4_part2(None);
     // println!("day4_part2: {}", ret4_2);
-    // let ret5 = day5_part1();
+    // let ret5 = day5_part1(None);
     // println!("day5_part1: {}", ret5);
-    // let ret5_2 = day5_part2();
+    // let ret5_2 = day5_part2(None);
     // println!("day5_part2: {}", ret5_2);
-    // let ret6 = day6_part1();
+    // let ret6 = day6_part1(None);
     // println!("day6_part1: {}", ret6);
-    // let ret6_2 = day6_part2();
+    // let ret6_2 = day6_part2(None);
     // println!("day6_part2: {}", ret6_2);
-    // let ret7 = day7_part1();
+    // let ret7 = day7_part1(None);
     // println!("day7_part1: {}", ret7);
-    // let ret7_2 = day7_part2();
+    // let ret7_2 = day7_part2(None);
     // println!("day7_part2: {}", ret7_2);
-    // let ret8 = day8_part1();
+    // let ret8 = day8_part1(None);
     // println!("day8_part1: {}", ret8);
-    // let ret8_2 = day8_part2();
+    // let ret8_2 = day8_part2(None);
     // println!("day8_part2: {}", ret8_2);
-    // let ret9 = day9_part1();
+    // let ret9 = day9_part1(None);
     // println!("day9_part1: {}", ret9);
-    // let ret9_2 = day9_part2();
+    // let ret9_2 = day9_part2(None);
     // println!("day9_part2: {}", ret9_2);
-    // let ret10 = day10_part1();
+    // let ret10 = day10_part1(None);
     // println!("day10_part1: {}", ret10);
-    // let ret10_2 = day10_part2();
+    // let ret10_2 = day10_part2(None);
     // println!("day10_part2: {}", ret10_2);
-    // let ret11 = day11_part1();
+    // let ret11 = day11_part1(None);
     // println!("day11_part1: {}", ret11);
-    // let ret11_2 = day11_part2();
+    // let ret11_2 = day11_part2(None);
     // println!("day11_part2: {}", ret11_2);
     // let ret12 = day12_part1(None);
     // println!("day12_part1: {}", ret12);
     // let ret12_2 = day12_part2(None);
     // println!("day12_part2: {}", ret12_2);
-    // let ret13 = day13_part1();
+    // let ret13 = day13_part1(None);
     // println!("day13_part1: {}", ret13);
-    // let ret13_2 = day13_part2();
+    // let ret13_2 = day13_part2(None);
     // println!("day13_part2: {}", ret13_2);
     // let ret14 = day14_part1(None);
     // println!("day14_part1: {}", ret14);
@@ -22,3 +22,12 @@ pub fn aoc_read_day_bytes(day: u8) -> Vec<u8> {
     file.read_to_end(&mut contents).unwrap();
     contents
 }
+
+// Deterministic numbers below `n` for randomized test fixtures, from a 64 bit LCG.
+#[cfg(test)]
+pub fn test_rng(mut seed: u64) -> impl FnMut(u64) -> u64 {
+    move |n| {
+        seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+        (seed >> 33) % n
+    }
+}